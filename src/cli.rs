@@ -0,0 +1,1403 @@
+//! The command line, parsed by clap into the Options of the library.
+
+use cgmemtime::{resolve_executable, Action, Options};
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use nix::libc;
+use nix::sys::resource::Resource;
+use nix::sys::signal;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(
+    allow_external_subcommands = true,
+    override_usage = "cgmemtime [OPTIONS] [--] <COMMAND> [ARGS]...",
+    after_help = "Option parsing stops at COMMAND: everything from there on, or after `--`, \
+        is passed to the command verbatim, e.g. `cgmemtime -t -- ls -t`. `--` also allows \
+        running a command named like one of the subcommands."
+)]
+pub struct Args {
+    #[arg(short = 'm', help = "Cgroup v2 base", default_value = "/sys/fs/cgroup")]
+    cg_fs_dir: String,
+    #[arg(
+        short = 'c',
+        help = "Cgroup to create the leaf in, relative paths are below the -m base"
+    )]
+    cg_dir: Option<String>,
+
+    #[arg(
+        action=ArgAction::SetTrue,
+        short='t',
+        conflicts_with = "stdout_format",
+        help="machine readable output (delimited columns), short for --stdout-format csv"
+    )]
+    machine_readable: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        short = 'j',
+        long,
+        conflicts_with_all = ["machine_readable", "stdout_format"],
+        help="JSON output, errors too (on stderr), short for --stdout-format json"
+    )]
+    json: bool,
+    #[arg(
+        short = 'd',
+        value_parser = parse_delim,
+        help = "column delimiter, nul for a NUL byte",
+        default_value = ";"
+    )]
+    delim: char,
+    #[arg(action=ArgAction::SetTrue, short='Z', help="disable falling back to systemd-run")]
+    disable_systemd_run: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        short = 'v',
+        long,
+        help="explain on stderr which cgroup is used and why"
+    )]
+    verbose: bool,
+    /// Don't create or touch any cgroup and run the command as a plain
+    /// child, e.g. where cgroups aren't accessible. Only the rusage figures
+    /// (user, sys, wall, child_RSS_high) are reported, like time(1) does,
+    /// they cover the command and the descendants it waited for.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="only report the rusage figures, without any cgroup",
+        long_help
+    )]
+    no_cgroup: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="don't fail at startup if memory.peak is missing (group peak is then reported as n/a)"
+    )]
+    allow_no_peak: bool,
+    #[arg(
+        long,
+        value_enum,
+        ignore_case = true,
+        help = "unit for memory figures [default: auto-scaled, bytes in csv]"
+    )]
+    unit: Option<MemUnit>,
+    /// What ends each csv row: a newline, a NUL byte or nothing. NUL
+    /// together with `-d nul` is safe for values containing newlines. With
+    /// none, rows collected via --append run into each other.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = RecordSep::Lf,
+        help = "terminator of csv rows",
+        long_help
+    )]
+    record_sep: RecordSep,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = TimeUnit::S,
+        help = "unit for times in csv, env, TOML and JSON output"
+    )]
+    time_unit: TimeUnit,
+    #[arg(
+        long = "label",
+        value_name = "NAME=VALUE",
+        value_parser = parse_label,
+        help = "tag the run with a label (repeatable)"
+    )]
+    labels: Vec<(String, String)>,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="also print cgmemtime's own resource usage (on stderr)"
+    )]
+    self_usage: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="print the human output as a single line of key=value pairs"
+    )]
+    oneline: bool,
+    #[arg(
+        long,
+        visible_alias = "format",
+        value_enum,
+        default_value_t = OutputFormat::Human,
+        help = "format on stdout"
+    )]
+    stdout_format: OutputFormat,
+    #[arg(long, help = "also write the result to FILE")]
+    output: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv, help = "format of --output")]
+    file_format: OutputFormat,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="show the run id (always in csv output) in the human output, too"
+    )]
+    show_run_id: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="add the hostname, kernel release and CPU count to the csv output"
+    )]
+    include_env: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="report the absolute path of the executable the command resolves to"
+    )]
+    show_exe: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="print the time spent creating and removing the cgroups (on stderr)"
+    )]
+    show_overhead: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "also send the result, formatted like --output, to the Unix socket PATH"
+    )]
+    socket: Option<PathBuf>,
+    #[arg(action=ArgAction::SetTrue, long, help="append to --output instead of truncating it")]
+    append: bool,
+    /// Also read memory.peak of the cgroup the leaf was created in. It
+    /// additionally includes whatever else ran there, e.g. cgmemtime itself
+    /// after a systemd-run re-exec, while the leaf holds only the command.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="report the memory peak of the leaf's parent next to the leaf's",
+        long_help
+    )]
+    nested_breakdown: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="show the memory.max, memory.high and memory.swap.max in force"
+    )]
+    show_limits: bool,
+    #[arg(long, value_parser = parse_duration, help = "terminate the command after DURATION")]
+    timeout: Option<Duration>,
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        default_value = "5s",
+        help = "on timeout, SIGKILL whatever is left in the cgroup DURATION after SIGTERM"
+    )]
+    kill_after: Duration,
+    /// Budget for the whole invocation, setup and teardown included. It's
+    /// checked between the phases, and --timeout and --wait-all are cut
+    /// short to fit it. Once it passed, whatever is left in the cgroup is
+    /// killed, the cgroups are removed as far as possible and cgmemtime
+    /// exits with 113.
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "abort cgmemtime altogether after DURATION",
+        long_help
+    )]
+    deadline: Option<Duration>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        help = "colorize the human output"
+    )]
+    color: ColorChoice,
+    /// The command becomes PID 1 of a new PID namespace, so its descendants
+    /// are killed when it exits. Requires CAP_SYS_ADMIN. Note that PID 1 only
+    /// receives signals it has a handler for, so on --timeout an unprepared
+    /// command ignores the SIGTERM and is killed via cgroup.kill after
+    /// --kill-after.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="run the command in a new PID namespace",
+        long_help
+    )]
+    pid_namespace: bool,
+    #[arg(
+        short = 'n',
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "run the command N times, each in a fresh leaf cgroup"
+    )]
+    iterations: u32,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="with -n, only print a run whose metrics changed from the previous one"
+    )]
+    summary_only_on_change: bool,
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 10.0,
+        help = "how much a metric has to change for --summary-only-on-change"
+    )]
+    change_threshold: f64,
+    /// Instead of a fixed -n, re-run the command in a fresh leaf until the
+    /// relative standard deviation of METRIC across the runs drops below
+    /// --stable-threshold, after at least 3 and at most --stable-max-runs
+    /// runs. The number of runs, mean and deviation are printed on stderr.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "METRIC",
+        conflicts_with = "iterations",
+        help = "run until METRIC stabilizes rather than a fixed -n times",
+        long_help
+    )]
+    until_stable: Option<StableMetric>,
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 5.0,
+        help = "relative standard deviation that --until-stable settles for"
+    )]
+    stable_threshold: f64,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 30,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "give up on --until-stable after N runs"
+    )]
+    stable_max_runs: u32,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        help = "re-run the command up to N times in a fresh leaf cgroup if it's OOM-killed"
+    )]
+    retry_on_oom: u32,
+    /// Read the command and its arguments from FILE, one per line or, with
+    /// --null, NUL-separated. This avoids quoting and keeps the arguments
+    /// out of the process list.
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "read the command and its arguments from FILE",
+        long_help
+    )]
+    command_file: Option<PathBuf>,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        requires = "command_file",
+        help="the --command-file arguments are NUL-separated"
+    )]
+    null: bool,
+    /// Run the command via `$SHELL -lc`, so that it sees the environment
+    /// set up by the user's login scripts, e.g. PATH. Note that the shell
+    /// and whatever its rc files start are measured, too.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="run the command in a login shell",
+        long_help
+    )]
+    login_shell: bool,
+    /// Pass NAME as the zeroth argument of the command rather than the
+    /// executable, e.g. for busybox-style multi-call binaries or `-bash`
+    /// for a login shell. With --login-shell it's the shell's.
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "run the command with NAME as argv[0]",
+        long_help
+    )]
+    argv0: Option<String>,
+    /// Clone the command like fork(2) does, i.e. with a copy of cgmemtime's
+    /// memory, instead of suspending cgmemtime until the exec. Slower, but
+    /// an escape hatch should vfork semantics trip up a libc or sanitizer.
+    /// The accounting is the same either way.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="clone the command without CLONE_VFORK",
+        long_help
+    )]
+    no_vfork: bool,
+    /// The signal the kernel sends cgmemtime when the command exits, for
+    /// embedding it where SIGCHLD is handled otherwise. The command is
+    /// reaped either way.
+    #[arg(
+        long,
+        value_name = "SIGNAL",
+        value_parser = parse_signal,
+        default_value = "SIGCHLD",
+        help = "signal to notify cgmemtime of the command's exit",
+        long_help
+    )]
+    exit_signal: signal::Signal,
+    /// Set a resource limit (soft and hard) of the command, like prlimit(1).
+    /// LIMIT is a number with an optional K, M, G or T suffix, or unlimited.
+    /// Unlike memory.max, exceeding e.g. AS makes allocations fail instead
+    /// of triggering the OOM killer.
+    #[arg(
+        long = "rlimit",
+        value_name = "NAME=LIMIT",
+        value_parser = parse_rlimit,
+        help = "set a resource limit of the command, e.g. AS=2G (repeatable)",
+        long_help
+    )]
+    rlimits: Vec<(Resource, libc::rlim_t)>,
+    #[arg(long, value_name = "FILE", help = "read the command's stdin from FILE")]
+    stdin: Option<PathBuf>,
+    #[arg(long, value_name = "FILE", help = "write the command's stdout to FILE")]
+    stdout: Option<PathBuf>,
+    #[arg(long, value_name = "FILE", help = "write the command's stderr to FILE")]
+    stderr: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "MS",
+        value_parser = parse_interval,
+        help = "poll memory.current every MS milliseconds (or a DURATION like 1s) while the command runs"
+    )]
+    sample_interval: Option<Duration>,
+    /// Kill the command once the cgroup's CPU usage (usage_usec in cpu.stat)
+    /// hasn't advanced for DURATION, i.e. it's stuck rather than slow.
+    /// Checked every --sample-interval, cgmemtime then exits with 122.
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "kill the command if it uses no CPU time for DURATION",
+        long_help
+    )]
+    hang_timeout: Option<Duration>,
+    /// Run /bin/true in a leaf of its own first and subtract its memory.peak,
+    /// i.e. what the kernel charges to any cgroup, from the group memory of
+    /// the command. Both the raw and the adjusted figure are reported.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        conflicts_with = "persist_cgroup",
+        help="subtract the group memory of a trivial command",
+        long_help
+    )]
+    subtract_baseline: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="show the current memory and CPU usage on stderr while the command runs"
+    )]
+    follow: bool,
+    /// Double the sample interval whenever the sampler's own CPU time
+    /// exceeds PERCENT of the time it has run, so that high-resolution
+    /// sampling doesn't distort the measurement, up to an interval of 1s.
+    /// The effective average interval is reported.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = parse_percent,
+        help = "cap the CPU overhead of sampling, e.g. 1%",
+        long_help
+    )]
+    max_sample_overhead: Option<f64>,
+    /// Run CMD with /bin/sh -c every 100ms after the command is started until
+    /// it succeeds, e.g. a health check of a server, and report the time
+    /// until then and memory.peak at that moment.
+    #[arg(
+        long,
+        value_name = "CMD",
+        help = "report when CMD first succeeds, e.g. a health check",
+        long_help
+    )]
+    ready_cmd: Option<String>,
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        default_value = "30s",
+        requires = "ready_cmd",
+        help = "give up --ready-cmd after DURATION"
+    )]
+    ready_timeout: Duration,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        requires = "ready_cmd",
+        help="terminate the command once --ready-cmd succeeds"
+    )]
+    stop_after_ready: bool,
+    /// Kill the command once the cgroup's memory.swap.current exceeds SIZE
+    /// (with a K, M, G or T suffix), checked every --sample-interval.
+    /// cgmemtime then exits with 125.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "kill the command if it swaps more than SIZE",
+        long_help
+    )]
+    abort_swap_above: Option<u64>,
+    /// Count the run as failed, and exit with 117, if the --threshold-metric
+    /// ended up above SIZE. Unlike --abort-swap-above the command isn't
+    /// stopped.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "fail if the memory used exceeds SIZE",
+        long_help
+    )]
+    fail_over: Option<u64>,
+    /// Cap the leaf's memory.max at SIZE, e.g. 512M, to reproduce an OOM
+    /// kill deterministically. Each run with -n, and each parallel job,
+    /// gets its own leaf with the same cap.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "set the leaf's memory.max to SIZE",
+        long_help
+    )]
+    memory_max: Option<u64>,
+    /// Cap the leaf's CPU time at QUOTA microseconds per PERIOD, e.g.
+    /// 50000/100000 for half a CPU, to measure the command while it's
+    /// throttled. QUOTA may be max, for no quota. Written to cpu.max, which
+    /// requires the cpu controller.
+    #[arg(
+        long,
+        value_name = "QUOTA/PERIOD",
+        value_parser = parse_cpu_max,
+        help = "set the leaf's cpu.max to QUOTA/PERIOD microseconds",
+        long_help
+    )]
+    cpu_max: Option<(Option<u64>, u64)>,
+    /// Confine the command to the CPUs in LIST, in the kernel's list
+    /// syntax such as 0-3,7, for reproducible benchmarks. Written to
+    /// cpuset.cpus, which requires the cpuset controller being delegated.
+    #[arg(
+        long,
+        value_name = "LIST",
+        value_parser = parse_cpuset,
+        help = "set the leaf's cpuset.cpus to LIST, e.g. 0-3,7",
+        long_help
+    )]
+    cpuset: Option<String>,
+    /// What --fail-over compares against: the leaf's memory.peak, the
+    /// rusage of the command, the higher of both or the leaf's
+    /// memory.swap.peak, which requires swap accounting.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "METRIC",
+        default_value_t = ThresholdMetric::PeakRss,
+        requires = "fail_over",
+        help = "the memory figure --fail-over applies to",
+        long_help
+    )]
+    threshold_metric: ThresholdMetric,
+    /// Count the run as failed, and exit with 114, if any of the given
+    /// memory.events counters of the leaf went up during the run, e.g.
+    /// `--fail-on-events high` for a workload that must fit under
+    /// memory.high without being throttled.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "EVENTS",
+        value_delimiter = ',',
+        help = "fail if any of the comma-separated memory.events occurred",
+        long_help
+    )]
+    fail_on_events: Vec<MemoryEvent>,
+    /// Additional sampled metrics. anon-peak is the highest anon figure of
+    /// memory.stat, i.e. memory without the page cache, which the kernel has
+    /// no peak of. Being sampled every --sample-interval it's approximate.
+    #[arg(
+        long = "metric",
+        value_enum,
+        help = "also sample METRIC (repeatable)",
+        long_help
+    )]
+    metrics: Vec<Metric>,
+    /// Sample /proc/PID/status of the command (not of its children) every
+    /// --sample-interval and report the highest VmPeak, VmHWM, VmData and
+    /// VmStk, e.g. to tell reserved address space from resident memory.
+    /// What happens between the last sample and the exit is missed.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="report the sampled Vm* figures of the command's /proc/PID/status",
+        long_help
+    )]
+    proc_status: bool,
+    /// Poll memory.events every --sample-interval and log each counter that
+    /// went up to FILE, as the seconds since the start, the counter's name,
+    /// its value and the increment. The counters read after the command
+    /// exited are logged too, so the last line of each counter matches the
+    /// end-of-run figure.
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "log when the memory.events counters go up to FILE",
+        long_help
+    )]
+    trace_events: Option<PathBuf>,
+    /// Write memory.current every --sample-interval to FILE, as csv with
+    /// the seconds since the start and the bytes, e.g. to plot how the
+    /// memory of a build grows.
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "write the memory.current samples to FILE as csv",
+        long_help
+    )]
+    trace_out: Option<PathBuf>,
+    /// Count the open file descriptors of all processes in the cgroup every
+    /// --sample-interval and report the highest count. Being sampled, it
+    /// may miss short-lived spikes.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="report the highest sampled number of open file descriptors",
+        long_help
+    )]
+    sample_fds: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="compare memory.peak against the sampled memory.current and warn if they diverge"
+    )]
+    cross_check: bool,
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 10.0,
+        help = "divergence that --cross-check tolerates"
+    )]
+    cross_check_tolerance: f64,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="after the command exits, wait for the rest of the cgroup to exit, too"
+    )]
+    wait_all: bool,
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        default_value = "30s",
+        help = "give up --wait-all after DURATION"
+    )]
+    wait_all_timeout: Duration,
+    /// After the command exits, keep measuring what it left running in the
+    /// cgroup, e.g. a daemon, for DURATION or until the cgroup is empty. The
+    /// figures then cover the whole window, the time spent is reported as
+    /// waited_all.
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "keep measuring for DURATION after the command exits",
+        long_help
+    )]
+    linger: Option<Duration>,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="keep the cgroup directories when cgmemtime fails, for inspection"
+    )]
+    no_cleanup_on_error: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        conflicts_with = "no_cgroup",
+        help="keep the cgroup directories after the run, for inspection"
+    )]
+    keep_cgroup: bool,
+    /// Print the schema version and the csv columns for the given options,
+    /// then exit. The version is bumped whenever columns are added, removed
+    /// or reordered.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="print the csv schema version and columns",
+        long_help
+    )]
+    schema_version: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="prefix csv output with a '# schema=N' line"
+    )]
+    emit_schema: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="report the descendant cgroup counts from cgroup.stat"
+    )]
+    cgroup_stat: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="report the anon and file memory per NUMA node from memory.numa_stat"
+    )]
+    numa: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="fail if the cgroup already contains processes"
+    )]
+    require_empty: bool,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="kill the processes in a leaf left over by an earlier run instead of failing"
+    )]
+    force: bool,
+
+    /// Account the command into the cgroup NAME, created below the -c
+    /// cgroup or user@$UID.service if it doesn't exist yet. It's kept after
+    /// cgmemtime exits, so the memory peak and CPU times accumulate over all
+    /// commands run in it until --report.
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "run the command in the persistent cgroup NAME",
+        long_help
+    )]
+    persist_cgroup: Option<String>,
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        requires = "persist_cgroup",
+        help="print the accumulated metrics of --persist-cgroup and remove it"
+    )]
+    report: bool,
+
+    /// Measure the already running process PID instead of a command: it's
+    /// moved into a fresh cgroup until it exits. Memory it allocated before
+    /// stays charged to its old cgroup, and as it's not cgmemtime's child the
+    /// times come from cpu.stat and child_RSS_high is unknown (0).
+    #[arg(
+        long,
+        value_name = "PID",
+        conflicts_with_all = [
+            "iterations",
+            "until_stable",
+            "retry_on_oom",
+            "subtract_baseline",
+            "persist_cgroup",
+            "pid_namespace",
+            "rlimits",
+            "login_shell",
+            "stdin",
+            "stdout",
+            "stderr",
+        ],
+        help = "measure the running process PID",
+        long_help
+    )]
+    attach: Option<libc::pid_t>,
+    /// Treat warnings, e.g. a --cross-check divergence or processes
+    /// surviving --wait-all, as errors. The result is still printed, but
+    /// cgmemtime then exits with 123.
+    #[arg(
+        action=ArgAction::SetTrue,
+        long,
+        help="fail the run on any warning",
+        long_help
+    )]
+    strict: bool,
+
+    #[command(subcommand)]
+    command: Option<SubCmd>,
+}
+
+fn parse_label(s: &str) -> std::result::Result<(String, String), String> {
+    let (name, value) = s.split_once('=').ok_or("expected NAME=VALUE")?;
+    if name.is_empty() {
+        return Err("empty label name".to_owned());
+    }
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// A single character, or nul for a NUL byte, which can't be passed as an
+/// argument.
+fn parse_delim(s: &str) -> std::result::Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        _ if s == "nul" => Ok('\0'),
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("expected a single character or nul, got '{s}'")),
+    }
+}
+
+/// A signal name, with or without the SIG prefix, or number.
+fn parse_signal(s: &str) -> std::result::Result<signal::Signal, String> {
+    let upper = s.to_ascii_uppercase();
+    let name = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{upper}")
+    };
+    s.parse::<i32>()
+        .map_or_else(|_| name.parse(), signal::Signal::try_from)
+        .map_err(|_| format!("invalid signal '{s}'"))
+}
+
+/// Plain (fractional) seconds or a number with a ms, s, m or h suffix.
+fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let (num, scale) = if let Some(num) = s.strip_suffix("ms") {
+        (num, 0.001)
+    } else if let Some(num) = s.strip_suffix('s') {
+        (num, 1.0)
+    } else if let Some(num) = s.strip_suffix('m') {
+        (num, 60.0)
+    } else if let Some(num) = s.strip_suffix('h') {
+        (num, 3600.0)
+    } else {
+        (s, 1.0)
+    };
+    let num: f64 = num.parse().map_err(|_| format!("invalid duration '{s}'"))?;
+    Duration::try_from_secs_f64(num * scale).map_err(|e| format!("invalid duration '{s}': {e}"))
+}
+
+/// A positive number of milliseconds, or a duration with a unit as for
+/// parse_duration().
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    let interval = match s.parse::<f64>() {
+        Ok(ms) => Duration::try_from_secs_f64(ms / 1000.0)
+            .map_err(|e| format!("invalid interval '{s}': {e}"))?,
+        Err(_) => parse_duration(s)?,
+    };
+    if interval.is_zero() {
+        return Err(format!("invalid interval '{s}': must be positive"));
+    }
+    Ok(interval)
+}
+
+/// A positive number with an optional % sign.
+fn parse_percent(s: &str) -> std::result::Result<f64, String> {
+    match s.strip_suffix('%').unwrap_or(s).parse::<f64>() {
+        Ok(percent) if percent > 0.0 => Ok(percent),
+        _ => Err(format!("invalid percentage '{s}'")),
+    }
+}
+
+/// A number with an optional K, M, G or T suffix (powers of 1024).
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let (num, factor) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        Some((i, 'T' | 't')) => (&s[..i], 1 << 40),
+        _ => (s, 1),
+    };
+    num.parse::<u64>()
+        .ok()
+        .and_then(|num| num.checked_mul(factor))
+        .ok_or(format!("invalid size '{s}'"))
+}
+
+/// QUOTA/PERIOD in microseconds, within the kernel's bounds for cpu.max.
+/// None is max, i.e. no quota.
+fn parse_cpu_max(s: &str) -> std::result::Result<(Option<u64>, u64), String> {
+    let (quota, period) = s.split_once('/').ok_or(format!(
+        "expected QUOTA/PERIOD, e.g. 50000/100000, got '{s}'"
+    ))?;
+    let parse = |v: &str| {
+        v.parse::<u64>()
+            .map_err(|_| format!("invalid number '{v}' in '{s}'"))
+    };
+    let quota = match quota {
+        "max" => None,
+        quota => Some(parse(quota)?),
+    };
+    let period = parse(period)?;
+    if !(1000..=1_000_000).contains(&period) {
+        return Err(format!("PERIOD must be 1000 to 1000000, got {period}"));
+    }
+    if let Some(quota) = quota.filter(|&quota| quota < 1000) {
+        return Err(format!("QUOTA must be at least 1000, got {quota}"));
+    }
+    Ok((quota, period))
+}
+
+/// A CPU list like 0-3,7, as cpuset.cpus takes it.
+fn parse_cpuset(s: &str) -> std::result::Result<String, String> {
+    let parse = |v: &str| {
+        v.parse::<u32>()
+            .map_err(|_| format!("invalid CPU '{v}' in '{s}', expected a list like 0-3,7"))
+    };
+    for item in s.split(',') {
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!("invalid range '{item}' in '{s}'"));
+                }
+            }
+            None => {
+                parse(item)?;
+            }
+        }
+    }
+    Ok(s.to_owned())
+}
+
+/// NAME=LIMIT, e.g. AS=2G or NOFILE=unlimited.
+fn parse_rlimit(s: &str) -> std::result::Result<(Resource, libc::rlim_t), String> {
+    let (name, limit) = s.split_once('=').ok_or("expected NAME=LIMIT")?;
+    let resource = match name.to_ascii_uppercase().trim_start_matches("RLIMIT_") {
+        "AS" => Resource::RLIMIT_AS,
+        "CORE" => Resource::RLIMIT_CORE,
+        "CPU" => Resource::RLIMIT_CPU,
+        "DATA" => Resource::RLIMIT_DATA,
+        "FSIZE" => Resource::RLIMIT_FSIZE,
+        "MEMLOCK" => Resource::RLIMIT_MEMLOCK,
+        "NOFILE" => Resource::RLIMIT_NOFILE,
+        "NPROC" => Resource::RLIMIT_NPROC,
+        "RSS" => Resource::RLIMIT_RSS,
+        "STACK" => Resource::RLIMIT_STACK,
+        _ => {
+            return Err(format!(
+                "unsupported resource '{name}', expected one of \
+                 AS, CORE, CPU, DATA, FSIZE, MEMLOCK, NOFILE, NPROC, RSS, STACK"
+            ))
+        }
+    };
+    let limit = if limit == "unlimited" {
+        libc::RLIM_INFINITY
+    } else {
+        parse_size(limit)?
+    };
+    Ok((resource, limit))
+}
+
+/// A clap counterpart of the library enum of the same name, which doesn't
+/// depend on clap.
+macro_rules! value_enum {
+    ($(#[$attr:meta])* $name:ident { $($(#[$variant_attr:meta])* $variant:ident),* $(,)? }) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+        $(#[$attr])*
+        enum $name {
+            $($(#[$variant_attr])* $variant,)*
+        }
+
+        impl From<$name> for cgmemtime::$name {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => cgmemtime::$name::$variant,)*
+                }
+            }
+        }
+    };
+}
+
+value_enum!(MemUnit {
+    #[value(name = "B")]
+    B,
+    #[value(name = "KiB")]
+    KiB,
+    #[value(name = "MiB")]
+    MiB,
+    #[value(name = "GiB")]
+    GiB,
+    #[value(name = "KB")]
+    KB,
+    #[value(name = "MB")]
+    MB,
+    #[value(name = "GB")]
+    GB,
+});
+
+value_enum!(TimeUnit {
+    /// fractional seconds
+    S,
+    Ms,
+    Us,
+    Ns,
+});
+
+value_enum!(RecordSep { Lf, Nul, None });
+
+value_enum!(OutputFormat {
+    /// one metric per line
+    Human,
+    /// a single row of delimited columns (cf. -d)
+    Csv,
+    /// shell-sourceable CGMEMTIME_NAME=value assignments
+    Env,
+    /// a TOML table per run, in bytes and --time-unit
+    Toml,
+    /// a JSON object per line, in bytes and --time-unit
+    Json,
+});
+
+value_enum!(ColorChoice {
+    /// when stdout is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+});
+
+value_enum!(Metric { AnonPeak });
+
+value_enum!(
+    #[value(rename_all = "snake_case")]
+    ThresholdMetric {
+        CgRss,
+        ChildRss,
+        PeakRss,
+        CgSwap,
+    }
+);
+
+value_enum!(
+    #[value(rename_all = "snake_case")]
+    MemoryEvent {
+        Low,
+        High,
+        Max,
+        Oom,
+        OomKill,
+        OomGroupKill,
+    }
+);
+
+value_enum!(StableMetric {
+    Wall,
+    User,
+    Sys,
+    PeakRss,
+    GroupMem,
+});
+
+#[derive(Subcommand, Debug)]
+enum SubCmd {
+    /// Print which metrics can be measured on this host
+    Capabilities,
+    /// Run shell command lines concurrently, each in a leaf cgroup of its
+    /// own, and print a result, labeled job=N, per command as it finishes
+    Parallel {
+        #[arg(
+            short = 'j',
+            long,
+            value_name = "N",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "run up to N commands at once"
+        )]
+        jobs: u32,
+        #[arg(
+            help = "command lines for /bin/sh -c, read from stdin (one per line) if none are given"
+        )]
+        commands: Vec<String>,
+    },
+    /// Print the current metrics of an existing cgroup without running anything
+    Inspect { path: PathBuf },
+    /// Print the change of each figure between two results saved with
+    /// `--file-format env` or `json`, the last result in each file counts
+    Diff { old: PathBuf, new: PathBuf },
+    /// Print a JSON Schema of the --json output, in the --time-unit given
+    #[command(hide = true)]
+    Schema,
+    #[command(external_subcommand)]
+    Variant(Vec<String>),
+}
+
+impl Args {
+    fn check_labels(&mut self) -> &mut Self {
+        for (name, _) in &self.labels {
+            if name.contains(self.delim) || name.contains(char::is_whitespace) {
+                Args::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        format!("label name '{name}' must not contain the delimiter or whitespace"),
+                    )
+                    .exit();
+            }
+        }
+        self
+    }
+
+    fn check_persist_cgroup(&mut self) -> &mut Self {
+        if let Some(name) = &self.persist_cgroup {
+            if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                Args::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        format!("--persist-cgroup '{name}' must be a plain directory name"),
+                    )
+                    .exit();
+            }
+        }
+        self
+    }
+
+    /// Turn the --command-file into the command.
+    fn read_command_file(&mut self) -> &mut Self {
+        let Some(path) = &self.command_file else {
+            return self;
+        };
+        let fail = |kind, msg: String| Args::command().error(kind, msg).exit();
+        if self.command.is_some() {
+            fail(
+                ErrorKind::ArgumentConflict,
+                "--command-file can't be combined with a command".to_owned(),
+            );
+        }
+        let buf = fs::read_to_string(path)
+            .unwrap_or_else(|err| fail(ErrorKind::Io, format!("{}: {err}", path.display())));
+        let argv: Vec<String> = if self.null {
+            buf.split('\0').map(str::to_owned).collect()
+        } else {
+            buf.lines().map(str::to_owned).collect()
+        };
+        // a trailing separator doesn't start another argument
+        let argv = match argv.split_last() {
+            Some((last, rest)) if last.is_empty() => rest.to_vec(),
+            _ => argv,
+        };
+        if argv.is_empty() {
+            fail(
+                ErrorKind::ValueValidation,
+                format!("--command-file {} is empty", path.display()),
+            );
+        }
+        if resolve_executable(&argv[0]).is_none() {
+            fail(
+                ErrorKind::ValueValidation,
+                format!("{} isn't an executable (or isn't in PATH)", argv[0]),
+            );
+        }
+        self.command = Some(SubCmd::Variant(argv));
+        self
+    }
+
+    /// Reject the options that parallel doesn't support, they either
+    /// require a single leaf or a sampler thread, which mustn't run while
+    /// the next command is cloned.
+    fn check_parallel(&mut self) -> &mut Self {
+        if !matches!(self.command, Some(SubCmd::Parallel { .. })) {
+            return self;
+        }
+        let unsupported = [
+            ("--timeout", self.timeout.is_some()),
+            ("--deadline", self.deadline.is_some()),
+            ("--hang-timeout", self.hang_timeout.is_some()),
+            ("--sample-interval", self.sample_interval.is_some()),
+            ("--follow", self.follow),
+            ("--cross-check", self.cross_check),
+            ("--sample-fds", self.sample_fds),
+            ("--metric", !self.metrics.is_empty()),
+            ("--abort-swap-above", self.abort_swap_above.is_some()),
+            ("--proc-status", self.proc_status),
+            ("--trace-events", self.trace_events.is_some()),
+            ("--trace-out", self.trace_out.is_some()),
+            ("--fail-over", self.fail_over.is_some()),
+            ("--fail-on-events", !self.fail_on_events.is_empty()),
+            ("--ready-cmd", self.ready_cmd.is_some()),
+            ("--iterations", self.iterations > 1),
+            ("--until-stable", self.until_stable.is_some()),
+            ("--retry-on-oom", self.retry_on_oom > 0),
+            ("--subtract-baseline", self.subtract_baseline),
+            ("--persist-cgroup", self.persist_cgroup.is_some()),
+            ("--login-shell", self.login_shell),
+            ("--stdin", self.stdin.is_some()),
+            ("--stdout", self.stdout.is_some()),
+            ("--stderr", self.stderr.is_some()),
+        ];
+        for (option, used) in unsupported {
+            if used {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("{option} can't be used with parallel"),
+                    )
+                    .exit();
+            }
+        }
+        self
+    }
+
+    fn check_threshold_metric(&mut self) -> &mut Self {
+        if self.attach.is_some() && self.threshold_metric == ThresholdMetric::ChildRss {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--threshold-metric child_rss can't be used with --attach, there's no rusage",
+                )
+                .exit();
+        }
+        self
+    }
+
+    fn check_no_cgroup(&mut self) -> &mut Self {
+        if !self.no_cgroup {
+            return self;
+        }
+        let unsupported = [
+            ("-c", self.cg_dir.is_some()),
+            ("--attach", self.attach.is_some()),
+            ("--persist-cgroup", self.persist_cgroup.is_some()),
+            ("--report", self.report),
+            ("--require-empty", self.require_empty),
+            ("--subtract-baseline", self.subtract_baseline),
+            ("--retry-on-oom", self.retry_on_oom > 0),
+            ("--timeout", self.timeout.is_some()),
+            ("--deadline", self.deadline.is_some()),
+            ("--hang-timeout", self.hang_timeout.is_some()),
+            ("--ready-cmd", self.ready_cmd.is_some()),
+            ("--wait-all", self.wait_all),
+            ("--linger", self.linger.is_some()),
+            ("--sample-interval", self.sample_interval.is_some()),
+            ("--follow", self.follow),
+            ("--cross-check", self.cross_check),
+            ("--sample-fds", self.sample_fds),
+            ("--metric", !self.metrics.is_empty()),
+            ("--abort-swap-above", self.abort_swap_above.is_some()),
+            ("--proc-status", self.proc_status),
+            ("--trace-events", self.trace_events.is_some()),
+            ("--trace-out", self.trace_out.is_some()),
+            (
+                "--threshold-metric cg_rss",
+                self.threshold_metric == ThresholdMetric::CgRss,
+            ),
+            (
+                "--threshold-metric cg_swap",
+                self.threshold_metric == ThresholdMetric::CgSwap,
+            ),
+            ("--fail-on-events", !self.fail_on_events.is_empty()),
+            ("--memory-max", self.memory_max.is_some()),
+            ("--cpu-max", self.cpu_max.is_some()),
+            ("--cpuset", self.cpuset.is_some()),
+            ("--show-limits", self.show_limits),
+            ("--cgroup-stat", self.cgroup_stat),
+            ("--numa", self.numa),
+            ("--nested-breakdown", self.nested_breakdown),
+            (
+                "capabilities",
+                matches!(self.command, Some(SubCmd::Capabilities)),
+            ),
+            (
+                "parallel",
+                matches!(self.command, Some(SubCmd::Parallel { .. })),
+            ),
+        ];
+        for (option, used) in unsupported {
+            if used {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("{option} can't be used with --no-cgroup"),
+                    )
+                    .exit();
+            }
+        }
+        self
+    }
+
+    /// Reject a command where there's none to run, and the lack of one
+    /// where there is.
+    fn check_command(&mut self) -> &mut Self {
+        if self.schema_version || self.report {
+            return self;
+        }
+        if self.attach.is_some() && self.command.is_some() {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--attach can't be combined with a command",
+                )
+                .exit();
+        }
+        if self.command.is_none() && self.attach.is_none() {
+            Args::command()
+                .error(
+                    ErrorKind::MissingSubcommand,
+                    "a command to measure is required",
+                )
+                .exit();
+        }
+        if self.argv0.is_some() && !matches!(self.command, Some(SubCmd::Variant(_))) {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--argv0 requires a command to pass it to",
+                )
+                .exit();
+        }
+        self
+    }
+
+    fn into_options(self) -> Options {
+        let stdout_format = if self.machine_readable {
+            OutputFormat::Csv
+        } else if self.json {
+            OutputFormat::Json
+        } else {
+            self.stdout_format
+        };
+        Options {
+            cg_fs_dir: self.cg_fs_dir,
+            cg_dir: self.cg_dir,
+            delim: self.delim,
+            disable_systemd_run: self.disable_systemd_run,
+            verbose: self.verbose,
+            no_cgroup: self.no_cgroup,
+            allow_no_peak: self.allow_no_peak,
+            unit: self.unit.map(Into::into),
+            record_sep: self.record_sep.into(),
+            time_unit: self.time_unit.into(),
+            labels: self.labels,
+            self_usage: self.self_usage,
+            oneline: self.oneline,
+            stdout_format: stdout_format.into(),
+            output: self.output,
+            file_format: self.file_format.into(),
+            show_run_id: self.show_run_id,
+            include_env: self.include_env,
+            show_exe: self.show_exe,
+            show_overhead: self.show_overhead,
+            socket: self.socket,
+            append: self.append,
+            nested_breakdown: self.nested_breakdown,
+            show_limits: self.show_limits,
+            timeout: self.timeout,
+            kill_after: self.kill_after,
+            deadline: self.deadline,
+            color: self.color.into(),
+            pid_namespace: self.pid_namespace,
+            iterations: self.iterations,
+            summary_only_on_change: self.summary_only_on_change,
+            change_threshold: self.change_threshold,
+            until_stable: self.until_stable.map(Into::into),
+            stable_threshold: self.stable_threshold,
+            stable_max_runs: self.stable_max_runs,
+            retry_on_oom: self.retry_on_oom,
+            login_shell: self.login_shell,
+            argv0: self.argv0,
+            no_vfork: self.no_vfork,
+            exit_signal: self.exit_signal,
+            rlimits: self.rlimits,
+            stdin: self.stdin,
+            stdout: self.stdout,
+            stderr: self.stderr,
+            sample_interval: self.sample_interval,
+            hang_timeout: self.hang_timeout,
+            subtract_baseline: self.subtract_baseline,
+            follow: self.follow,
+            max_sample_overhead: self.max_sample_overhead,
+            ready_cmd: self.ready_cmd,
+            ready_timeout: self.ready_timeout,
+            stop_after_ready: self.stop_after_ready,
+            abort_swap_above: self.abort_swap_above,
+            fail_over: self.fail_over,
+            memory_max: self.memory_max,
+            cpu_max: self.cpu_max,
+            cpuset: self.cpuset,
+            threshold_metric: self.threshold_metric.into(),
+            fail_on_events: self.fail_on_events.into_iter().map(Into::into).collect(),
+            metrics: self.metrics.into_iter().map(Into::into).collect(),
+            proc_status: self.proc_status,
+            trace_events: self.trace_events,
+            trace_out: self.trace_out,
+            sample_fds: self.sample_fds,
+            cross_check: self.cross_check,
+            cross_check_tolerance: self.cross_check_tolerance,
+            wait_all: self.wait_all,
+            wait_all_timeout: self.wait_all_timeout,
+            linger: self.linger,
+            no_cleanup_on_error: self.no_cleanup_on_error,
+            keep_cgroup: self.keep_cgroup,
+            schema_version: self.schema_version,
+            emit_schema: self.emit_schema,
+            cgroup_stat: self.cgroup_stat,
+            numa: self.numa,
+            require_empty: self.require_empty,
+            force: self.force,
+            persist_cgroup: self.persist_cgroup,
+            report: self.report,
+            attach: self.attach,
+            strict: self.strict,
+            action: self.command.map(SubCmd::into_action),
+        }
+    }
+}
+
+impl SubCmd {
+    fn into_action(self) -> Action {
+        match self {
+            SubCmd::Capabilities => Action::Capabilities,
+            SubCmd::Parallel { jobs, commands } => Action::Parallel { jobs, commands },
+            SubCmd::Inspect { path } => Action::Inspect(path),
+            SubCmd::Diff { old, new } => Action::Diff { old, new },
+            SubCmd::Schema => Action::Schema,
+            SubCmd::Variant(argv) => Action::Run(argv),
+        }
+    }
+}
+
+/// The command line interface, exits the process once done.
+pub fn cli(mut args: Args) -> ! {
+    args.check_labels()
+        .check_persist_cgroup()
+        .check_parallel()
+        .check_no_cgroup()
+        .check_threshold_metric()
+        .read_command_file()
+        .check_command();
+    exit(cgmemtime::invoke(args.into_options()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The command and its arguments, as parsed from `argv`.
+    fn parse_command(argv: &[&str]) -> (Args, Vec<String>) {
+        let args = Args::try_parse_from(argv).unwrap();
+        let Some(SubCmd::Variant(command)) = &args.command else {
+            panic!("no command in {argv:?}: {:?}", args.command);
+        };
+        let command = command.clone();
+        (args, command)
+    }
+
+    #[test]
+    fn command_after_double_dash() {
+        let (args, command) = parse_command(&["cgmemtime", "-t", "--", "ls", "-t"]);
+        assert!(args.machine_readable);
+        assert_eq!(command, ["ls", "-t"]);
+    }
+
+    #[test]
+    fn command_flags_colliding_with_ours() {
+        // option parsing stops at the command, -- or not
+        let (args, command) = parse_command(&["cgmemtime", "ls", "-t"]);
+        assert!(!args.machine_readable);
+        assert_eq!(command, ["ls", "-t"]);
+
+        let (args, command) =
+            parse_command(&["cgmemtime", "-d", ",", "--", "cut", "-d", ":", "-f", "1"]);
+        assert_eq!(args.delim, ',');
+        assert!(!args.disable_systemd_run);
+        assert_eq!(command, ["cut", "-d", ":", "-f", "1"]);
+
+        let (args, command) = parse_command(&["cgmemtime", "-Z", "--", "grep", "-Z", "--", "x"]);
+        assert!(args.disable_systemd_run);
+        assert_eq!(command, ["grep", "-Z", "--", "x"]);
+    }
+
+    #[test]
+    fn format_is_stdout_format() {
+        let (args, _) = parse_command(&["cgmemtime", "--format", "json", "true"]);
+        assert_eq!(args.stdout_format, OutputFormat::Json);
+        // and conflicts like it
+        assert!(Args::try_parse_from(["cgmemtime", "-t", "--format", "env", "true"]).is_err());
+    }
+
+    #[test]
+    fn command_named_like_a_subcommand() {
+        let (_, command) = parse_command(&["cgmemtime", "--", "diff", "a", "b"]);
+        assert_eq!(command, ["diff", "a", "b"]);
+    }
+}
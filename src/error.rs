@@ -0,0 +1,193 @@
+//! Why a measurement couldn't be set up or taken.
+
+use nix::libc;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    /// nothing given to run()
+    NoCommand,
+    /// the -m base isn't a cgroup filesystem
+    NoCgroupFs(PathBuf),
+    /// the -m base is a v1 hierarchy, with the v2 one at `unified` in
+    /// hybrid mode
+    CgroupV1 {
+        dir: PathBuf,
+        unified: Option<PathBuf>,
+    },
+    /// the controller isn't in the cgroup.controllers or
+    /// cgroup.subtree_control `file`
+    NoController {
+        controller: &'static str,
+        file: PathBuf,
+    },
+    /// -c, --persist-cgroup or a stale leaf can't be used
+    BadCgroup(String),
+    /// /proc/self/cgroup isn't below a .service and -Z is given
+    NoServiceCgroup(String),
+    /// systemd-run couldn't be executed
+    SystemdRun(io::Error),
+    /// memory.peak or memory.swap.peak doesn't exist
+    NoPeak {
+        file: PathBuf,
+        requires: &'static str,
+        hint: &'static str,
+    },
+    /// --require-empty found processes in `dir`
+    NotEmpty {
+        dir: PathBuf,
+        pids: Vec<libc::pid_t>,
+    },
+    /// a syscall or an operation on a (cgroup) file failed
+    Io { what: String, err: io::Error },
+    /// the command couldn't be executed, the message is the child's
+    Exec(String),
+    /// --deadline passed during `phase`
+    Deadline {
+        deadline: Duration,
+        phase: &'static str,
+    },
+}
+
+impl Error {
+    /// For map_err(), `what` failed with the io::Error.
+    pub(crate) fn io(what: String) -> impl FnOnce(io::Error) -> Error {
+        move |err| Error::Io { what, err }
+    }
+
+    /// For map_err(), the content `what` is about can't be parsed.
+    pub(crate) fn invalid<E>(what: String) -> impl FnOnce(E) -> Error
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        move |err| Error::Io {
+            what,
+            err: io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+
+    /// Names the kind of failure for scripts, stable across releases unlike
+    /// the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NoCommand => "no_command",
+            Error::NoCgroupFs(_) => "no_cgroup_fs",
+            Error::CgroupV1 { .. } => "cgroup_v1",
+            Error::NoController { .. } => "no_controller",
+            Error::BadCgroup(_) => "bad_cgroup",
+            Error::NoServiceCgroup(_) => "no_service_cgroup",
+            Error::SystemdRun(_) => "systemd_run",
+            Error::NoPeak { .. } => "no_peak",
+            Error::NotEmpty { .. } => "not_empty",
+            Error::Io { .. } => "io",
+            Error::Exec(_) => "exec",
+            Error::Deadline { .. } => "deadline",
+        }
+    }
+
+    /// The cgroup directory or file the error is about, if it's a single one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::NoCgroupFs(dir) | Error::CgroupV1 { dir, .. } | Error::NotEmpty { dir, .. } => {
+                Some(dir)
+            }
+            Error::NoController { file, .. } | Error::NoPeak { file, .. } => Some(file),
+            _ => None,
+        }
+    }
+
+    /// The exit status of cgmemtime, distinct per kind of failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::NoCommand => 2,
+            Error::NoController { .. } => 110,
+            Error::BadCgroup(_) => 111,
+            Error::Io { .. } => 112,
+            Error::NoCgroupFs(_) => 115,
+            Error::CgroupV1 { .. } => 116,
+            Error::SystemdRun(_) => 118,
+            Error::NoServiceCgroup(_) => 119,
+            Error::NoPeak { .. } => 120,
+            Error::NotEmpty { .. } => 121,
+            Error::Deadline { .. } => 113,
+            // as the shell's
+            Error::Exec(_) => 127,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoCommand => write!(f, "a command to measure is required"),
+            Error::NoCgroupFs(dir) => write!(
+                f,
+                "No cgroup filesystem mounted at {} - cf. -m option",
+                dir.display()
+            ),
+            Error::CgroupV1 { dir, unified } => {
+                write!(
+                    f,
+                    "cgroup v2 unified hierarchy required; {} appears to use v1",
+                    dir.display()
+                )?;
+                match unified {
+                    Some(unified) => write!(
+                        f,
+                        "\nThe host is in hybrid mode - try -m {}",
+                        unified.display()
+                    ),
+                    None => Ok(()),
+                }
+            }
+            Error::NoController { controller, file } => {
+                write!(
+                    f,
+                    "Cgroup {controller} controller isn't in {}",
+                    file.display()
+                )
+            }
+            Error::BadCgroup(msg) => write!(f, "{msg}"),
+            Error::NoServiceCgroup(own_cg) => write!(
+                f,
+                "Couldn't find user@$UID.service cgroup in {own_cg} - cf. -c option"
+            ),
+            Error::SystemdRun(err) => write!(f, "Can't execute systemd-run: {err}"),
+            Error::NoPeak {
+                file,
+                requires,
+                hint,
+            } => write!(
+                f,
+                "{} doesn't exist (requires {requires}) - cf. {hint}",
+                file.display()
+            ),
+            Error::NotEmpty { dir, pids } => {
+                let pids: Vec<String> = pids.iter().map(|pid| pid.to_string()).collect();
+                write!(
+                    f,
+                    "{} isn't empty, it contains PIDs {} - cf. --require-empty option",
+                    dir.display(),
+                    pids.join(" ")
+                )
+            }
+            Error::Io { what, err } => write!(f, "{what}: {err}"),
+            Error::Exec(msg) => write!(f, "{msg}"),
+            Error::Deadline { deadline, phase } => {
+                write!(f, "--deadline of {deadline:?} passed during {phase}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::SystemdRun(err) | Error::Io { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
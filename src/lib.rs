@@ -0,0 +1,3113 @@
+use clone3::Clone3;
+use nix::errno::Errno;
+use nix::fcntl;
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::resource::{self, Resource};
+use nix::sys::signal;
+use nix::sys::stat::Mode;
+use nix::sys::statfs;
+use nix::sys::utsname;
+use nix::unistd::Pid;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::{metadata, read_dir, File};
+use std::io;
+use std::io::{BufWriter, IsTerminal, Read, Write};
+use std::ops::ControlFlow;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::SystemTime;
+use std::time::{Duration, Instant};
+use tempfile::Builder;
+
+mod error;
+mod output;
+mod sampler;
+pub use error::Error;
+use output::FormatOpts;
+pub use output::{ColorChoice, MemUnit, OutputFormat, RecordSep, TimeUnit};
+use sampler::{EventTrace, Probes, Progress, Sampler};
+pub use sampler::{ProcStatus, Sample};
+
+const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// how often an EBUSY write to cgroup.subtree_control is retried, with the
+/// pause doubling from 10ms
+const SUBTREE_CONTROL_RETRIES: u32 = 5;
+/// --until-stable doesn't judge the spread of fewer runs
+const MIN_STABLE_RUNS: usize = 3;
+
+/// What cgmemtime is asked to do, the library counterpart of the
+/// command line with a field per option. The defaults are the command
+/// line's.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// cgroup v2 base, cf. -m
+    pub cg_fs_dir: String,
+    /// cgroup to create the leaf in, relative paths are below the -m base,
+    /// cf. -c
+    pub cg_dir: Option<String>,
+    /// column delimiter, nul for a NUL byte, cf. -d
+    pub delim: char,
+    /// disable falling back to systemd-run, cf. -Z
+    pub disable_systemd_run: bool,
+    /// explain on stderr which cgroup is used and why, cf. --verbose
+    pub verbose: bool,
+    /// only report the rusage figures, without any cgroup, cf. --no-cgroup
+    pub no_cgroup: bool,
+    /// don't fail at startup if memory.peak is missing (group peak is then
+    /// reported as n/a), cf. --allow-no-peak
+    pub allow_no_peak: bool,
+    /// unit for memory figures, auto-scaled in the human output and bytes
+    /// in the csv without one, cf. --unit
+    pub unit: Option<MemUnit>,
+    /// terminator of csv rows, cf. --record-sep
+    pub record_sep: RecordSep,
+    /// unit for times in csv, env, TOML and JSON output, cf. --time-unit
+    pub time_unit: TimeUnit,
+    /// tag the run with a label, cf. --label
+    pub labels: Vec<(String, String)>,
+    /// also print cgmemtime's own resource usage (on stderr), cf. --self-usage
+    pub self_usage: bool,
+    /// print the human output as a single line of key=value pairs,
+    /// cf. --oneline
+    pub oneline: bool,
+    /// format on stdout, cf. --stdout-format
+    pub stdout_format: OutputFormat,
+    /// also write the result to FILE, cf. --output
+    pub output: Option<PathBuf>,
+    /// format of --output, cf. --file-format
+    pub file_format: OutputFormat,
+    /// show the run id (always in csv output) in the human output, too,
+    /// cf. --show-run-id
+    pub show_run_id: bool,
+    /// add the hostname, kernel release and CPU count to the csv output,
+    /// cf. --include-env
+    pub include_env: bool,
+    /// report the absolute path of the executable the command resolves to,
+    /// cf. --show-exe
+    pub show_exe: bool,
+    /// print the time spent creating and removing the cgroups (on stderr),
+    /// cf. --show-overhead
+    pub show_overhead: bool,
+    /// also send the result, formatted like --output, to the Unix socket PATH,
+    /// cf. --socket
+    pub socket: Option<PathBuf>,
+    /// append to --output instead of truncating it, cf. --append
+    pub append: bool,
+    /// report the memory peak of the leaf's parent next to the leaf's,
+    /// cf. --nested-breakdown
+    pub nested_breakdown: bool,
+    /// show the memory.max, memory.high and memory.swap.max in force,
+    /// cf. --show-limits
+    pub show_limits: bool,
+    /// terminate the command after DURATION, cf. --timeout
+    pub timeout: Option<Duration>,
+    /// on timeout, SIGKILL whatever is left in the cgroup DURATION after
+    /// SIGTERM, cf. --kill-after
+    pub kill_after: Duration,
+    /// abort cgmemtime altogether after DURATION, cf. --deadline
+    pub deadline: Option<Duration>,
+    /// colorize the human output, cf. --color
+    pub color: ColorChoice,
+    /// run the command in a new PID namespace, cf. --pid-namespace
+    pub pid_namespace: bool,
+    /// run the command N times, each in a fresh leaf cgroup, cf. -n
+    pub iterations: u32,
+    /// with -n, only print a run whose metrics changed from the previous one,
+    /// cf. --summary-only-on-change
+    pub summary_only_on_change: bool,
+    /// how much a metric has to change for --summary-only-on-change,
+    /// cf. --change-threshold
+    pub change_threshold: f64,
+    /// run until METRIC stabilizes rather than a fixed -n times,
+    /// cf. --until-stable
+    pub until_stable: Option<StableMetric>,
+    /// relative standard deviation that --until-stable settles for,
+    /// cf. --stable-threshold
+    pub stable_threshold: f64,
+    /// give up on --until-stable after N runs, cf. --stable-max-runs
+    pub stable_max_runs: u32,
+    /// re-run the command up to N times in a fresh leaf cgroup if it's
+    /// OOM-killed, cf. --retry-on-oom
+    pub retry_on_oom: u32,
+    /// run the command in a login shell, cf. --login-shell
+    pub login_shell: bool,
+    /// run the command with NAME as argv[0], cf. --argv0
+    pub argv0: Option<String>,
+    /// clone the command without CLONE_VFORK, cf. --no-vfork
+    pub no_vfork: bool,
+    /// signal to notify cgmemtime of the command's exit, cf. --exit-signal
+    pub exit_signal: signal::Signal,
+    /// set a resource limit of the command, e.g. AS=2G, cf. --rlimit
+    pub rlimits: Vec<(Resource, libc::rlim_t)>,
+    /// read the command's stdin from FILE, cf. --stdin
+    pub stdin: Option<PathBuf>,
+    /// write the command's stdout to FILE, cf. --stdout
+    pub stdout: Option<PathBuf>,
+    /// write the command's stderr to FILE, cf. --stderr
+    pub stderr: Option<PathBuf>,
+    /// poll memory.current every MS milliseconds (or a DURATION like 1s) while
+    /// the command runs, cf. --sample-interval
+    pub sample_interval: Option<Duration>,
+    /// kill the command if it uses no CPU time for DURATION, cf. --hang-timeout
+    pub hang_timeout: Option<Duration>,
+    /// subtract the group memory of a trivial command, cf. --subtract-baseline
+    pub subtract_baseline: bool,
+    /// show the current memory and CPU usage on stderr while the command runs,
+    /// cf. --follow
+    pub follow: bool,
+    /// cap the CPU overhead of sampling, e.g. 1%, cf. --max-sample-overhead
+    pub max_sample_overhead: Option<f64>,
+    /// report when CMD first succeeds, e.g. a health check, cf. --ready-cmd
+    pub ready_cmd: Option<String>,
+    /// give up --ready-cmd after DURATION, cf. --ready-timeout
+    pub ready_timeout: Duration,
+    /// terminate the command once --ready-cmd succeeds, cf. --stop-after-ready
+    pub stop_after_ready: bool,
+    /// kill the command if it swaps more than SIZE, cf. --abort-swap-above
+    pub abort_swap_above: Option<u64>,
+    /// fail if the memory used exceeds SIZE, cf. --fail-over
+    pub fail_over: Option<u64>,
+    /// set the leaf's memory.max to SIZE, cf. --memory-max
+    pub memory_max: Option<u64>,
+    /// set the leaf's cpu.max to QUOTA/PERIOD microseconds, cf. --cpu-max
+    pub cpu_max: Option<(Option<u64>, u64)>,
+    /// set the leaf's cpuset.cpus to LIST, e.g. 0-3,7, cf. --cpuset
+    pub cpuset: Option<String>,
+    /// the memory figure --fail-over applies to, cf. --threshold-metric
+    pub threshold_metric: ThresholdMetric,
+    /// fail if any of the comma-separated memory.events occurred,
+    /// cf. --fail-on-events
+    pub fail_on_events: Vec<MemoryEvent>,
+    /// also sample METRIC, cf. --metric
+    pub metrics: Vec<Metric>,
+    /// report the sampled Vm* figures of the command's /proc/PID/status,
+    /// cf. --proc-status
+    pub proc_status: bool,
+    /// log when the memory.events counters go up to FILE, cf. --trace-events
+    pub trace_events: Option<PathBuf>,
+    /// write the memory.current samples to FILE as csv, cf. --trace-out
+    pub trace_out: Option<PathBuf>,
+    /// report the highest sampled number of open file descriptors,
+    /// cf. --sample-fds
+    pub sample_fds: bool,
+    /// compare memory.peak against the sampled memory.current and warn if they
+    /// diverge, cf. --cross-check
+    pub cross_check: bool,
+    /// divergence that --cross-check tolerates, cf. --cross-check-tolerance
+    pub cross_check_tolerance: f64,
+    /// after the command exits, wait for the rest of the cgroup to exit, too,
+    /// cf. --wait-all
+    pub wait_all: bool,
+    /// give up --wait-all after DURATION, cf. --wait-all-timeout
+    pub wait_all_timeout: Duration,
+    /// keep measuring for DURATION after the command exits, cf. --linger
+    pub linger: Option<Duration>,
+    /// keep the cgroup directories when cgmemtime fails, for inspection,
+    /// cf. --no-cleanup-on-error
+    pub no_cleanup_on_error: bool,
+    /// keep the cgroup directories after the run, for inspection,
+    /// cf. --keep-cgroup
+    pub keep_cgroup: bool,
+    /// print the csv schema version and columns, cf. --schema-version
+    pub schema_version: bool,
+    /// prefix csv output with a '# schema=N' line, cf. --emit-schema
+    pub emit_schema: bool,
+    /// report the descendant cgroup counts from cgroup.stat, cf. --cgroup-stat
+    pub cgroup_stat: bool,
+    /// report the anon and file memory per NUMA node from memory.numa_stat,
+    /// cf. --numa
+    pub numa: bool,
+    /// fail if the cgroup already contains processes, cf. --require-empty
+    pub require_empty: bool,
+    /// kill the processes in a leaf left over by an earlier run instead of
+    /// failing, cf. --force
+    pub force: bool,
+
+    /// run the command in the persistent cgroup NAME, cf. --persist-cgroup
+    pub persist_cgroup: Option<String>,
+    /// print the accumulated metrics of --persist-cgroup and remove it,
+    /// cf. --report
+    pub report: bool,
+
+    /// measure the running process PID, cf. --attach
+    pub attach: Option<libc::pid_t>,
+    /// fail the run on any warning, cf. --strict
+    pub strict: bool,
+    /// what to do, measuring a command unless it's None with --attach,
+    /// --report or --schema-version
+    pub action: Option<Action>,
+}
+
+/// What to do with the options, the subcommands of the command line.
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// measure the command and its arguments, executed without a shell
+    Run(Vec<String>),
+    /// print which metrics can be measured on this host, cf. capabilities
+    Capabilities,
+    /// run the command lines with /bin/sh -c concurrently, each in a leaf
+    /// of its own, read from stdin if there are none, cf. parallel
+    Parallel { jobs: u32, commands: Vec<String> },
+    /// print the current metrics of an existing cgroup, cf. inspect
+    Inspect(PathBuf),
+    /// print the change of each figure between two saved results, cf. diff
+    Diff { old: PathBuf, new: PathBuf },
+    /// print a JSON Schema of the --json output, cf. schema
+    Schema,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            cg_fs_dir: "/sys/fs/cgroup".to_owned(),
+            cg_dir: None,
+            delim: ';',
+            disable_systemd_run: false,
+            verbose: false,
+            no_cgroup: false,
+            allow_no_peak: false,
+            unit: None,
+            record_sep: RecordSep::Lf,
+            time_unit: TimeUnit::S,
+            labels: Vec::new(),
+            self_usage: false,
+            oneline: false,
+            stdout_format: OutputFormat::Human,
+            output: None,
+            file_format: OutputFormat::Csv,
+            show_run_id: false,
+            include_env: false,
+            show_exe: false,
+            show_overhead: false,
+            socket: None,
+            append: false,
+            nested_breakdown: false,
+            show_limits: false,
+            timeout: None,
+            kill_after: Duration::from_secs(5),
+            deadline: None,
+            color: ColorChoice::Auto,
+            pid_namespace: false,
+            iterations: 1,
+            summary_only_on_change: false,
+            change_threshold: 10.0,
+            until_stable: None,
+            stable_threshold: 5.0,
+            stable_max_runs: 30,
+            retry_on_oom: 0,
+            login_shell: false,
+            argv0: None,
+            no_vfork: false,
+            exit_signal: signal::Signal::SIGCHLD,
+            rlimits: Vec::new(),
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            sample_interval: None,
+            hang_timeout: None,
+            subtract_baseline: false,
+            follow: false,
+            max_sample_overhead: None,
+            ready_cmd: None,
+            ready_timeout: Duration::from_secs(30),
+            stop_after_ready: false,
+            abort_swap_above: None,
+            fail_over: None,
+            memory_max: None,
+            cpu_max: None,
+            cpuset: None,
+            threshold_metric: ThresholdMetric::PeakRss,
+            fail_on_events: Vec::new(),
+            metrics: Vec::new(),
+            proc_status: false,
+            trace_events: None,
+            trace_out: None,
+            sample_fds: false,
+            cross_check: false,
+            cross_check_tolerance: 10.0,
+            wait_all: false,
+            wait_all_timeout: Duration::from_secs(30),
+            linger: None,
+            no_cleanup_on_error: false,
+            keep_cgroup: false,
+            schema_version: false,
+            emit_schema: false,
+            cgroup_stat: false,
+            numa: false,
+            require_empty: false,
+            force: false,
+            persist_cgroup: None,
+            report: false,
+            attach: None,
+            strict: false,
+            action: None,
+        }
+    }
+}
+
+/// The options and the state of one invocation, the cgroups it created
+/// are removed when it's dropped.
+#[derive(Clone, Debug)]
+struct Session {
+    options: Options,
+    temp_cg_dir: Option<PathBuf>,
+    leaf_dir: Option<PathBuf>,
+    /// the cgroup of --persist-cgroup, neither it nor its leaf is removed
+    persist_dir: Option<PathBuf>,
+    /// set right before a successful exit, cf. --no-cleanup-on-error
+    succeeded: bool,
+    /// identifies the rows of this invocation, cf. new_run_id()
+    run_id: String,
+    /// read at startup with --include-env
+    env: Option<Env>,
+    /// time taken to create the cgroups, for --show-overhead
+    setup_overhead: Duration,
+    /// a warning was raised with --strict
+    warned: Cell<bool>,
+    /// when --deadline passes
+    deadline_at: Option<Instant>,
+    /// the dispositions invoke() replaced, restored in the child before the
+    /// exec
+    child_sigactions: Vec<(signal::Signal, signal::SigAction)>,
+}
+
+impl Session {
+    fn new(options: Options) -> Session {
+        Session {
+            deadline_at: options.deadline.map(|deadline| Instant::now() + deadline),
+            options,
+            temp_cg_dir: None,
+            leaf_dir: None,
+            persist_dir: None,
+            succeeded: false,
+            run_id: new_run_id(),
+            env: None,
+            setup_overhead: Duration::ZERO,
+            warned: Cell::new(false),
+            child_sigactions: Vec::new(),
+        }
+    }
+}
+
+/// Write `+controller` to a cgroup.subtree_control file, returns the error
+/// and the number of retries on failure.
+fn enable_controller(
+    sub_ctl_file: &Path,
+    controller: &str,
+) -> std::result::Result<(), (io::Error, u32)> {
+    let mut file = File::options()
+        .write(true)
+        .open(sub_ctl_file)
+        .map_err(|err| (err, 0))?;
+    // EBUSY is transient while another tool briefly has processes in the
+    // cgroup or reconfigures the tree
+    let mut pause = Duration::from_millis(10);
+    let mut retries = 0;
+    loop {
+        match file.write_all(format!("+{controller}").as_bytes()) {
+            Ok(()) => return Ok(()),
+            Err(err)
+                if err.raw_os_error() == Some(libc::EBUSY) && retries < SUBTREE_CONTROL_RETRIES =>
+            {
+                thread::sleep(pause);
+                pause *= 2;
+                retries += 1;
+            }
+            Err(err) => return Err((err, retries)),
+        }
+    }
+}
+
+/// Fail unless `controller` is delegated to `cg_dir`, i.e. its interface
+/// files are there.
+fn check_controller(cg_dir: &Path, controller: &'static str) -> std::result::Result<(), Error> {
+    let controllers_file = cg_dir.join("cgroup.controllers");
+    let controllers = read_cg_value(&controllers_file).unwrap_or_default();
+    if !has_controller(&controllers, controller) {
+        return Err(Error::NoController {
+            controller,
+            file: controllers_file,
+        });
+    }
+    Ok(())
+}
+
+/// Enable `controller` for the children of `cg_dir`, failing if it isn't
+/// delegated to it.
+fn require_controller(cg_dir: &Path, controller: &'static str) -> std::result::Result<(), Error> {
+    check_controller(cg_dir, controller)?;
+    let sub_ctl_file = cg_dir.join("cgroup.subtree_control");
+    enable_controller(&sub_ctl_file, controller).map_err(|(err, retries)| Error::Io {
+        what: format!(
+            "Write to file {} failed after {retries} retries (it contains '{}')",
+            sub_ctl_file.display(),
+            read_cg_value(&sub_ctl_file).unwrap_or_default()
+        ),
+        err,
+    })
+}
+
+/// The -c directory, which is below the -m base unless it's absolute, e.g.
+/// -c my.slice/bench.
+fn resolve_cg_dir(cg_fs_dir: &str, cg_dir: &str) -> String {
+    if cg_dir.starts_with('/') {
+        cg_dir.to_owned()
+    } else {
+        Path::new(cg_fs_dir)
+            .join(cg_dir)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Controller lists (cgroup.controllers, cgroup.subtree_control) are
+/// space separated and terminated by a newline.
+fn has_controller(list: &str, controller: &str) -> bool {
+    list.split_whitespace().any(|c| c == controller)
+}
+
+/// An additionally sampled metric for --metric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    AnonPeak,
+}
+
+/// What --fail-over compares against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThresholdMetric {
+    CgRss,
+    ChildRss,
+    PeakRss,
+    CgSwap,
+}
+
+/// A memory.events counter for --fail-on-events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryEvent {
+    Low,
+    High,
+    Max,
+    Oom,
+    OomKill,
+    OomGroupKill,
+}
+
+impl MemoryEvent {
+    /// The key in memory.events
+    fn name(self) -> &'static str {
+        match self {
+            MemoryEvent::Low => "low",
+            MemoryEvent::High => "high",
+            MemoryEvent::Max => "max",
+            MemoryEvent::Oom => "oom",
+            MemoryEvent::OomKill => "oom_kill",
+            MemoryEvent::OomGroupKill => "oom_group_kill",
+        }
+    }
+}
+
+/// What --until-stable watches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StableMetric {
+    Wall,
+    User,
+    Sys,
+    PeakRss,
+    GroupMem,
+}
+
+impl StableMetric {
+    fn of(self, r: &Result) -> f64 {
+        match self {
+            StableMetric::Wall => r.child_wall.as_secs_f64(),
+            StableMetric::User => r.child_user.as_secs_f64(),
+            StableMetric::Sys => r.child_sys.as_secs_f64(),
+            StableMetric::PeakRss => r.peak_rss as f64,
+            StableMetric::GroupMem => r.cg_rss_highwater.unwrap_or_default() as f64,
+        }
+    }
+
+    /// As given on the command line
+    fn name(self) -> &'static str {
+        match self {
+            StableMetric::Wall => "wall",
+            StableMetric::User => "user",
+            StableMetric::Sys => "sys",
+            StableMetric::PeakRss => "peak-rss",
+            StableMetric::GroupMem => "group-mem",
+        }
+    }
+
+    fn format(self, value: f64, opts: &FormatOpts) -> String {
+        match self {
+            StableMetric::Wall | StableMetric::User | StableMetric::Sys => {
+                format!("{:?}", Duration::from_secs_f64(value))
+            }
+            StableMetric::PeakRss | StableMetric::GroupMem => opts.mem(value as i64),
+        }
+    }
+}
+
+/// Mean, sample standard deviation and the latter in percent of the
+/// former.
+fn spread(values: &[f64]) -> (f64, f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    let stddev = var.sqrt();
+    let rsd = if stddev == 0.0 {
+        0.0
+    } else {
+        stddev * 100.0 / mean.abs().max(f64::MIN_POSITIVE)
+    };
+    (mean, stddev, rsd)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Limit {
+    Bytes(i64),
+    Max,
+}
+
+impl Limit {
+    fn format(self, opts: &FormatOpts) -> String {
+        match self {
+            Limit::Bytes(bytes) => opts.mem(bytes),
+            Limit::Max => "max".to_owned(),
+        }
+    }
+
+    fn value(self, opts: &FormatOpts) -> String {
+        match self {
+            Limit::Bytes(bytes) => opts.mem_value(bytes),
+            Limit::Max => "max".to_owned(),
+        }
+    }
+}
+
+/// The lowest limit set in `file` on the way from `leaf` up to `base`,
+/// i.e. the one that is effectively in force for the leaf.
+///
+/// None if no cgroup on the way has the file (e.g. without swap accounting).
+fn effective_limit(
+    leaf: &Path,
+    base: &Path,
+    file: &str,
+) -> std::result::Result<Option<Limit>, Error> {
+    let mut limit = None;
+    for dir in leaf.ancestors().take_while(|dir| dir.starts_with(base)) {
+        let path = dir.join(file);
+        let Ok(buf) = read_cg_value(&path) else {
+            continue;
+        };
+        let value = match buf.as_str() {
+            "max" => Limit::Max,
+            v => Limit::Bytes(v.parse().map_err(Error::invalid(format!(
+                "Can't parse {v} in {}",
+                path.display()
+            )))?),
+        };
+        limit = Some(limit.map_or(value, |l: Limit| l.min(value)));
+    }
+    Ok(limit)
+}
+
+#[derive(Debug)]
+pub struct Limits {
+    pub memory_max: Option<Limit>,
+    pub memory_high: Option<Limit>,
+    pub memory_swap_max: Option<Limit>,
+}
+
+#[derive(Debug)]
+pub struct CgroupStat {
+    pub nr_descendants: u64,
+    pub nr_dying_descendants: u64,
+}
+
+/// Where the measurement was taken, for --include-env.
+#[derive(Clone, Debug)]
+struct Env {
+    hostname: String,
+    kernel: String,
+    ncpu: usize,
+}
+
+impl Env {
+    fn read() -> std::result::Result<Env, Error> {
+        let uts = utsname::uname().map_err(|errno| Error::Io {
+            what: "uname failed".to_owned(),
+            err: errno.into(),
+        })?;
+        Ok(Env {
+            hostname: uts.nodename().to_string_lossy().into_owned(),
+            kernel: uts.release().to_string_lossy().into_owned(),
+            ncpu: thread::available_parallelism().map_or(1, |n| n.get()),
+        })
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct NumaNode {
+    pub anon: u64,
+    pub file: u64,
+}
+
+#[derive(Debug)]
+pub struct Ready {
+    /// since the command was started
+    pub after: Duration,
+    /// memory.peak at that moment
+    pub mem: Option<i64>,
+    /// it was terminated then, due to --stop-after-ready
+    pub stopped: bool,
+}
+
+#[derive(Debug)]
+pub struct Timeout {
+    /// processes that ignored SIGTERM for --kill-after
+    pub killed_hard: usize,
+}
+
+/// The leaf's keyed stat files as they were after the command exited.
+#[derive(Default, Debug)]
+pub struct Snapshot {
+    pub memory_stat: HashMap<String, u64>,
+    pub memory_events: HashMap<String, u64>,
+    pub cpu_stat: HashMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Missing or unreadable files, e.g. while a killed cgroup drains, are
+    /// left empty.
+    fn read(leaf_dir: &Path) -> Snapshot {
+        let read = |file: &str| read_keyed(&leaf_dir.join(file)).unwrap_or_default();
+        Snapshot {
+            memory_stat: read("memory.stat"),
+            memory_events: read("memory.events"),
+            cpu_stat: read("cpu.stat"),
+        }
+    }
+}
+
+/// The figures of a run. Memory is in bytes throughout, MemUnit only
+/// converts it for the output.
+#[derive(Default, Debug)]
+pub struct Result {
+    pub child_user: Duration,
+    pub child_sys: Duration,
+    pub child_wall: Duration,
+    /// ru_maxrss, which Linux reports in KiB, in bytes
+    pub child_rss_highwater: i64,
+    /// memory.peak, already in bytes
+    pub cg_rss_highwater: Option<i64>,
+    /// memory.swap.peak, None without the swap controller
+    pub cg_swap_peak: Option<i64>,
+    /// ru_minflt and ru_majflt, None without rusage (--attach)
+    pub minor_faults: Option<u64>,
+    pub major_faults: Option<u64>,
+    /// ru_nvcsw and ru_nivcsw, None without rusage (--attach)
+    pub vol_ctxsw: Option<u64>,
+    pub invol_ctxsw: Option<u64>,
+    /// usage_usec, user_usec and system_usec from the leaf's cpu.stat,
+    /// which include descendants the command didn't wait for
+    pub cg_cpu_usage: Option<Duration>,
+    pub cg_cpu_user: Option<Duration>,
+    pub cg_cpu_system: Option<Duration>,
+    /// nr_throttled and throttled_usec from the leaf's cpu.stat, only read
+    /// with --cpu-max
+    pub nr_throttled: Option<u64>,
+    pub throttled: Option<Duration>,
+    /// pids.peak of the leaf, None without the pids controller
+    pub pids_peak: Option<u64>,
+    /// the higher of child_rss_highwater and cg_rss_highwater
+    pub peak_rss: i64,
+    /// the file the command resolved to with --show-exe
+    pub exe: Option<PathBuf>,
+    /// memory.peak of the leaf's parent with --nested-breakdown
+    pub parent_peak: Option<i64>,
+    /// highest memory.current seen with --sample-interval
+    pub sampled_peak: Option<i64>,
+    /// when sampled_peak was seen, relative to the start of the command
+    pub sampled_peak_at: Option<Duration>,
+    pub limits: Option<Limits>,
+    /// None with --cgroup-stat if cgroup.stat couldn't be read
+    pub cgroup_stat: Option<CgroupStat>,
+    pub timeout: Option<Timeout>,
+    /// how long --wait-all waited for the remaining processes
+    pub waited_all: Option<Duration>,
+    /// oom counter from memory.events, the times the limit was hit and
+    /// reclaim failed
+    pub ooms: u64,
+    /// oom_kill counter from memory.events
+    pub oom_kills: u64,
+    /// runs it took with --retry-on-oom
+    pub attempts: Option<u32>,
+    /// per node with --numa, None if memory.numa_stat couldn't be read
+    pub numa: Option<BTreeMap<u32, NumaNode>>,
+    /// when --ready-cmd first succeeded
+    pub ready: Option<Ready>,
+    /// the --hang-timeout watchdog fired
+    pub hung: bool,
+    /// memory.swap.current when --abort-swap-above fired
+    pub swap_abort: Option<i64>,
+    /// the --threshold-metric that exceeded --fail-over
+    pub fail_over: Option<i64>,
+    /// effective sample interval with --max-sample-overhead
+    pub sample_interval_avg: Option<Duration>,
+    /// highest anon seen with --metric anon-peak
+    pub sampled_anon_peak: Option<i64>,
+    /// sampled from /proc/PID/status with --proc-status
+    pub proc_status: Option<ProcStatus>,
+    /// highest number of open fds seen with --sample-fds
+    pub sampled_fds: Option<u64>,
+    /// group memory of /bin/true with --subtract-baseline
+    pub baseline: Option<i64>,
+    /// the signal the command was killed by
+    pub term_signal: Option<i32>,
+    /// None if it was killed or isn't our child (--attach)
+    pub exit_code: Option<i32>,
+    /// the command couldn't be executed, it then exits with 127
+    pub exec_error: Option<String>,
+    pub snapshot: Snapshot,
+    /// memory.events right before the run, for --fail-on-events
+    pub events_before: HashMap<String, u64>,
+    /// the --fail-on-events counters that went up, and by how much
+    pub tripped_events: Vec<(String, u64)>,
+}
+
+impl Result {
+    /// The figures of a child reaped by wait4().
+    fn from_wait(started: Instant, status: i32, usg: libc::rusage) -> Result {
+        Result {
+            child_user: timeval_to_duration(usg.ru_utime),
+            child_sys: timeval_to_duration(usg.ru_stime),
+            child_wall: started.elapsed(),
+            child_rss_highwater: usg.ru_maxrss * 1024,
+            minor_faults: Some(usg.ru_minflt as u64),
+            major_faults: Some(usg.ru_majflt as u64),
+            vol_ctxsw: Some(usg.ru_nvcsw as u64),
+            invol_ctxsw: Some(usg.ru_nivcsw as u64),
+            term_signal: libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status)),
+            exit_code: libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)),
+            ..Default::default()
+        }
+    }
+
+    /// Why the command doesn't count as successful, None if it exited with
+    /// 0 and wasn't killed, OOM-killed or timed out.
+    pub fn failure(&self) -> Option<String> {
+        if self.timeout.is_some() {
+            Some("timed out".to_owned())
+        } else if self.hung {
+            Some("hung".to_owned())
+        } else if self.swap_abort.is_some() {
+            Some("swap above limit".to_owned())
+        } else if self.fail_over.is_some() {
+            Some("memory above --fail-over".to_owned())
+        } else if !self.tripped_events.is_empty() {
+            Some("memory.events tripped".to_owned())
+        } else if self.oom_kills > 0 {
+            Some(format!("{} OOM kill(s)", self.oom_kills))
+        } else if let Some(err) = &self.exec_error {
+            Some(err.clone())
+        } else if let Some(sig) = self
+            .term_signal
+            // expected after --stop-after-ready
+            .filter(|_| !self.ready.as_ref().is_some_and(|ready| ready.stopped))
+        {
+            Some(format!("killed by signal {sig}"))
+        } else {
+            match self.exit_code {
+                Some(0) | None => None,
+                Some(code) => Some(format!("exit code {code}")),
+            }
+        }
+    }
+
+    /// The failing exit status a shell would report for the command,
+    /// 128 + the signal if it was killed. None if it succeeded or was
+    /// stopped after --ready-cmd as asked.
+    pub fn shell_status(&self) -> Option<i32> {
+        if self.ready.as_ref().is_some_and(|ready| ready.stopped) {
+            return None;
+        }
+        match (self.term_signal, self.exit_code) {
+            (Some(sig), _) => Some(128 + sig),
+            (None, Some(0) | None) => None,
+            (None, Some(code)) => Some(code),
+        }
+    }
+
+    /// cg_rss_highwater minus the --subtract-baseline
+    fn adjusted_group_mem(&self) -> Option<i64> {
+        let baseline = self.baseline.unwrap_or_default();
+        self.cg_rss_highwater.map(|cg| (cg - baseline).max(0))
+    }
+
+    /// Whether any time or memory figure differs from `prev` by more than
+    /// `threshold` percent.
+    fn changed_from(&self, prev: &Result, threshold: f64) -> bool {
+        let exceeds = |prev: f64, cur: f64| {
+            // a metric appearing from zero always counts as a change
+            let base = prev.abs().max(f64::MIN_POSITIVE);
+            (cur - prev).abs() * 100.0 / base > threshold
+        };
+        let secs = |d: Duration| d.as_secs_f64();
+        exceeds(secs(prev.child_user), secs(self.child_user))
+            || exceeds(secs(prev.child_sys), secs(self.child_sys))
+            || exceeds(secs(prev.child_wall), secs(self.child_wall))
+            || exceeds(
+                prev.child_rss_highwater as f64,
+                self.child_rss_highwater as f64,
+            )
+            || exceeds(
+                prev.cg_rss_highwater.unwrap_or_default() as f64,
+                self.cg_rss_highwater.unwrap_or_default() as f64,
+            )
+            || exceeds(prev.peak_rss as f64, self.peak_rss as f64)
+    }
+}
+
+/// Read a single-line cgroup file (memory.peak, memory.max, ...) without
+/// its trailing newline.
+///
+/// The whole file is read, rather than a fixed number of bytes, so that
+/// nothing is cut off no matter how wide the value is.
+fn read_cg_value(file: &Path) -> io::Result<String> {
+    let buf = fs::read_to_string(file)?;
+    Ok(buf.lines().next().unwrap_or_default().to_owned())
+}
+
+/// None if the file is missing or doesn't hold a number.
+fn read_cg_number<T: FromStr>(file: &Path) -> Option<T> {
+    read_cg_value(file).ok()?.parse().ok()
+}
+
+/// A command cloned into a leaf.
+struct Child {
+    pid: libc::pid_t,
+    pidfd: RawFd,
+    started: Instant,
+    /// why the exec failed, as the child reported it
+    exec_error: Option<String>,
+}
+
+/// What `cgmemtime capabilities` found in the leaf.
+struct Capabilities {
+    /// whether each interface file exists
+    files: Vec<(&'static str, bool)>,
+    /// whether each cpu.stat field is there
+    cpu_stat: Vec<(&'static str, bool)>,
+}
+
+/// Snapshot of an existing cgroup for `cgmemtime inspect`.
+#[derive(Default, Debug)]
+struct Inspection {
+    memory_current: Option<i64>,
+    memory_peak: Option<i64>,
+    cpu_usage: Option<Duration>,
+    cpu_user: Option<Duration>,
+    cpu_system: Option<Duration>,
+    io_read_bytes: Option<u64>,
+    io_write_bytes: Option<u64>,
+    pids_current: Option<u64>,
+}
+
+impl Inspection {
+    fn read(cg_dir: &Path) -> std::result::Result<Inspection, Error> {
+        read_dir(cg_dir).map_err(Error::io(format!(
+            "Can't open directory {}",
+            cg_dir.display()
+        )))?;
+        let cpu_stat = read_keyed(&cg_dir.join("cpu.stat")).unwrap_or_default();
+        let usec = |key: &str| cpu_stat.get(key).map(|&v| Duration::from_micros(v));
+        let io = read_io_stat(&cg_dir.join("io.stat")).ok();
+        Ok(Inspection {
+            memory_current: read_cg_number(&cg_dir.join("memory.current")),
+            memory_peak: read_cg_number(&cg_dir.join("memory.peak")),
+            cpu_usage: usec("usage_usec"),
+            cpu_user: usec("user_usec"),
+            cpu_system: usec("system_usec"),
+            io_read_bytes: io.map(|(read, _)| read),
+            io_write_bytes: io.map(|(_, write)| write),
+            pids_current: read_cg_number(&cg_dir.join("pids.current")),
+        })
+    }
+}
+
+/// Sum up the read and written bytes over all devices in io.stat.
+fn read_io_stat(file: &Path) -> io::Result<(u64, u64)> {
+    let mut total = (0, 0);
+    for line in fs::read_to_string(file)?.lines() {
+        // e.g. 8:0 rbytes=90430464 wbytes=299008 rios=8950 wios=12 dbytes=0 dios=0
+        for field in line.split_whitespace().skip(1) {
+            match field.split_once('=') {
+                Some(("rbytes", v)) => total.0 += v.parse::<u64>().unwrap_or_default(),
+                Some(("wbytes", v)) => total.1 += v.parse::<u64>().unwrap_or_default(),
+                _ => (),
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// Parse a flat keyed file like memory.events or cpu.stat.
+fn read_keyed(file: &Path) -> io::Result<HashMap<String, u64>> {
+    Ok(fs::read_to_string(file)?
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_owned(), value.trim().parse().ok()?))
+        })
+        .collect())
+}
+
+/// anon and file bytes per NUMA node from memory.numa_stat, whose lines
+/// look like `anon N0=1234 N1=5678`.
+fn read_numa_stat(file: &Path) -> io::Result<BTreeMap<u32, NumaNode>> {
+    let mut nodes: BTreeMap<u32, NumaNode> = BTreeMap::new();
+    for line in fs::read_to_string(file)?.lines() {
+        let mut fields = line.split_whitespace();
+        let key = fields.next().unwrap_or_default();
+        if key != "anon" && key != "file" {
+            continue;
+        }
+        for field in fields {
+            let Some((node, bytes)) = field
+                .strip_prefix('N')
+                .and_then(|field| field.split_once('='))
+            else {
+                continue;
+            };
+            let (Ok(node), Ok(bytes)) = (node.parse(), bytes.parse()) else {
+                continue;
+            };
+            let entry = nodes.entry(node).or_default();
+            if key == "anon" {
+                entry.anon = bytes;
+            } else {
+                entry.file = bytes;
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+/// The PIDs in the cgroup, none once it's removed.
+fn read_procs(cg_dir: &Path) -> std::result::Result<Vec<libc::pid_t>, Error> {
+    let file = cg_dir.join("cgroup.procs");
+    let buf = match fs::read_to_string(&file) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(Error::io(format!("Can't read {}", file.display()))(err)),
+    };
+    buf.lines()
+        .map(|pid| {
+            pid.parse().map_err(Error::invalid(format!(
+                "Can't parse {pid} in {}",
+                file.display()
+            )))
+        })
+        .collect()
+}
+
+/// Kill everything in the cgroup, including processes forked meanwhile.
+fn kill_cgroup(cg_dir: &Path) {
+    // cgroup.kill requires Kernel 5.14 or later
+    if fs::write(cg_dir.join("cgroup.kill"), "1").is_err() {
+        for pid in read_procs(cg_dir).unwrap_or_default() {
+            let _ = signal::kill(Pid::from_raw(pid), signal::Signal::SIGKILL);
+        }
+    }
+}
+
+/// Wait until the process behind `pidfd` has exited, returns false if it's
+/// still running after `timeout`. Without a timeout it waits indefinitely.
+fn wait_pidfd(pidfd: RawFd, timeout: Option<Duration>) -> std::result::Result<bool, Error> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let ms = left.map_or(-1, |left| {
+            left.as_millis().min(libc::c_int::MAX as u128) as libc::c_int
+        });
+        let mut fds = [PollFd::new(pidfd, PollFlags::POLLIN)];
+        match poll(&mut fds, ms) {
+            Ok(0) if left.is_some_and(|left| left.is_zero()) => return Ok(false),
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => return Ok(true),
+            Err(errno) => {
+                return Err(Error::Io {
+                    what: "poll on pidfd failed".to_owned(),
+                    err: errno.into(),
+                })
+            }
+        }
+    }
+}
+
+/// Unique enough to tell concurrent invocations apart on one host, e.g. when
+/// their rows end up in the same --output file.
+fn new_run_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{:x}-{:x}", now.as_nanos(), std::process::id())
+}
+
+/// The absolute path of the executable file `cmd` names, looked up in PATH
+/// unless it contains a slash, like the exec does.
+pub fn resolve_executable(cmd: &str) -> Option<PathBuf> {
+    let executable = |path: &Path| {
+        metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+    let path = if cmd.contains('/') {
+        Some(PathBuf::from(cmd)).filter(|path| executable(path))
+    } else {
+        let path = std::env::var_os("PATH")?;
+        std::env::split_paths(&path)
+            .map(|dir| dir.join(cmd))
+            .find(|path| executable(path))
+    };
+    fs::canonicalize(path?).ok()
+}
+
+/// Quote `arg` for a POSIX shell command line.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// The figures of the last result in a file written with `--file-format
+/// env` or `json`, named as the env variables.
+fn read_result_file(path: &Path) -> std::result::Result<Vec<(String, String)>, Error> {
+    let content =
+        fs::read_to_string(path).map_err(Error::io(format!("Can't read {}", path.display())))?;
+    if !content.trim_start().starts_with('{') {
+        return Ok(parse_env_vars(&content));
+    }
+    let last = content.lines().rev().find(|line| !line.trim().is_empty());
+    last.and_then(parse_json_fields).ok_or_else(|| {
+        Error::invalid(format!("Can't parse the last line of {}", path.display()))(
+            "not a JSON object as written by --file-format json",
+        )
+    })
+}
+
+/// The `CGMEMTIME_` variables of a file written with `--file-format env`,
+/// without the prefix and unquoted. A later assignment replaces an
+/// earlier one, as when sourcing the file.
+fn parse_env_vars(content: &str) -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    for line in content.lines() {
+        let Some((name, value)) = line
+            .strip_prefix("CGMEMTIME_")
+            .and_then(|line| line.split_once('='))
+        else {
+            continue;
+        };
+        // cf. shell_quote()
+        let value = match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+            Some(quoted) => quoted.replace(r"'\''", "'"),
+            None => value.to_owned(),
+        };
+        match vars.iter_mut().find(|(n, _)| n == name) {
+            Some(var) => var.1 = value,
+            None => vars.push((name.to_owned(), value)),
+        }
+    }
+    vars
+}
+
+/// The top-level fields of a single-line object of `--file-format json`,
+/// named and valued like the env variables, i.e. the keys spelled as for
+/// env and upper-cased, booleans as 1 or 0. Null fields count as missing,
+/// nested objects such as the labels are left out.
+fn parse_json_fields(line: &str) -> Option<Vec<(String, String)>> {
+    let mut chars = line.trim().chars().peekable();
+    if chars.next()? != '{' {
+        return None;
+    }
+    let mut fields = Vec::new();
+    loop {
+        match chars.next()? {
+            '}' => return Some(fields),
+            ',' => continue,
+            '"' => (),
+            _ => return None,
+        }
+        let key = parse_json_string(&mut chars)?;
+        if chars.next()? != ':' {
+            return None;
+        }
+        let value = match chars.peek()? {
+            '"' => {
+                chars.next();
+                Some(parse_json_string(&mut chars)?)
+            }
+            '{' => {
+                skip_json_object(&mut chars)?;
+                None
+            }
+            _ => {
+                let mut scalar = String::new();
+                while let Some(c) = chars.next_if(|&c| c != ',' && c != '}') {
+                    scalar.push(c);
+                }
+                match scalar.as_str() {
+                    "null" => None,
+                    "true" => Some("1".to_owned()),
+                    "false" => Some("0".to_owned()),
+                    _ => Some(scalar),
+                }
+            }
+        };
+        if let Some(value) = value {
+            fields.push((output::key_of_json(&key).to_uppercase(), value));
+        }
+    }
+}
+
+/// The rest of a JSON string after its opening quote, unescaped.
+fn parse_json_string(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(s),
+            '\\' => match chars.next()? {
+                'n' => s.push('\n'),
+                't' => s.push('\t'),
+                'r' => s.push('\r'),
+                'u' => {
+                    let hex: String = chars.take(4).collect();
+                    s.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                // \" \\ \/
+                c => s.push(c),
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+/// Skip a JSON object, nested ones included.
+fn skip_json_object(chars: &mut impl Iterator<Item = char>) -> Option<()> {
+    let mut depth = 0;
+    loop {
+        match chars.next()? {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some(()),
+            '}' => depth -= 1,
+            '"' => {
+                parse_json_string(chars)?;
+            }
+            _ => (),
+        }
+    }
+}
+
+fn timeval_to_duration(tv: libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+}
+
+/// Print the wrapper's own rusage, kept apart from the child's figures.
+fn print_self_usage(opts: &FormatOpts) -> std::result::Result<(), Error> {
+    let mut usg = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    let usg = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usg.as_mut_ptr()) < 0 {
+            return Err(Error::Io {
+                what: "getrusage failed".to_owned(),
+                err: io::Error::last_os_error(),
+            });
+        }
+        usg.assume_init()
+    };
+    eprintln!("self_user: {:?}", timeval_to_duration(usg.ru_utime));
+    eprintln!("self_sys: {:?}", timeval_to_duration(usg.ru_stime));
+    eprintln!("self_RSS_high: {}", opts.mem(usg.ru_maxrss * 1024));
+    Ok(())
+}
+
+impl Session {
+    fn check_cgroupfs(&mut self) -> std::result::Result<&mut Self, Error> {
+        let dir = Path::new(&self.options.cg_fs_dir);
+        match statfs::statfs(dir).map(|fs| fs.filesystem_type()) {
+            Ok(statfs::CGROUP2_SUPER_MAGIC) => (),
+            // a v1 hierarchy is a tmpfs of per-controller mounts
+            Ok(statfs::TMPFS_MAGIC | statfs::CGROUP_SUPER_MAGIC) => {
+                let unified = dir.join("unified");
+                let hybrid = statfs::statfs(&unified)
+                    .is_ok_and(|fs| fs.filesystem_type() == statfs::CGROUP2_SUPER_MAGIC);
+                return Err(Error::CgroupV1 {
+                    dir: dir.to_owned(),
+                    unified: hybrid.then_some(unified),
+                });
+            }
+            _ => return Err(Error::NoCgroupFs(dir.to_owned())),
+        }
+        let files = [
+            dir.join("cgroup.controllers"),
+            dir.join("cgroup.subtree_control"),
+        ];
+        for file in files {
+            let buf = read_cg_value(&file)
+                .map_err(Error::io(format!("Can't read file {}", file.display())))?;
+            if !has_controller(&buf, "memory") {
+                return Err(Error::NoController {
+                    controller: "memory",
+                    file,
+                });
+            }
+        }
+        Ok(self)
+    }
+
+    fn check_cgroup_dir(&mut self) -> std::result::Result<&mut Self, Error> {
+        if let Some(cg_dir) = &self.options.cg_dir {
+            self.options.cg_dir = Some(resolve_cg_dir(&self.options.cg_fs_dir, cg_dir));
+        }
+        match &self.options.cg_dir {
+            Some(cg_dir) => {
+                let Ok(meta) = metadata(cg_dir) else {
+                    return Err(Error::BadCgroup(format!(
+                        "Directory {cg_dir} does not exist."
+                    )));
+                };
+                if !meta.is_dir() {
+                    return Err(Error::BadCgroup(format!(
+                        "Path {cg_dir} is not a directory."
+                    )));
+                }
+                // a typo'd -c would otherwise only fail when memory.peak is read
+                let fs = statfs::statfs(cg_dir.as_str()).map_err(|errno| Error::Io {
+                    what: format!("Can't statfs {cg_dir}"),
+                    err: errno.into(),
+                })?;
+                if fs.filesystem_type() != statfs::CGROUP2_SUPER_MAGIC {
+                    return Err(Error::BadCgroup(format!(
+                        "Directory {cg_dir} isn't on a cgroup2 filesystem."
+                    )));
+                }
+                let base = fs::canonicalize(&self.options.cg_fs_dir).map_err(Error::io(
+                    format!("Can't resolve {}", self.options.cg_fs_dir),
+                ))?;
+                let canonical = fs::canonicalize(cg_dir)
+                    .map_err(Error::io(format!("Can't resolve {cg_dir}")))?;
+                if !canonical.starts_with(&base) {
+                    return Err(Error::BadCgroup(format!(
+                        "Directory {cg_dir} isn't below the cgroup v2 base {} - cf. -m option",
+                        self.options.cg_fs_dir
+                    )));
+                }
+                let cg_dir = PathBuf::from(cg_dir);
+                if self.options.attach.is_some() {
+                    // the -c cgroup may be populated, cf. setup_cgroup()
+                    self.create_temp_cg_dir(&cg_dir)?;
+                }
+                self.open_persist_cgroup(&cg_dir)
+            }
+            None => {
+                let mut buf = String::new();
+                File::open("/proc/self/cgroup")
+                    .and_then(|file| file.take(1024).read_to_string(&mut buf))
+                    .map_err(Error::io("Can't read /proc/self/cgroup".to_owned()))?;
+                let Some(s_pos) = buf.find("/") else {
+                    return Err(Error::BadCgroup(format!(
+                        "/proc/self/cgroup doesn't contain a slash: {}",
+                        buf.trim_end()
+                    )));
+                };
+                let s_pos = s_pos + 1;
+                match buf.find(".service") {
+                    Some(e_pos) => {
+                        let Some(p_dir) = buf.get(s_pos..(e_pos + ".service".len())) else {
+                            return Err(self.reexec_with_systemd_run(buf.trim_end()));
+                        };
+                        let p_dir = Path::new(self.options.cg_fs_dir.as_str()).join(p_dir);
+                        if self.options.verbose {
+                            eprintln!(
+                                "/proc/self/cgroup is {}: creating the cgroup below {}",
+                                buf.trim_end(),
+                                p_dir.display()
+                            );
+                        }
+                        if self.options.persist_cgroup.is_some() {
+                            return self.open_persist_cgroup(&p_dir);
+                        }
+                        self.create_temp_cg_dir(&p_dir)?;
+                    }
+                    None => return Err(self.reexec_with_systemd_run(buf.trim_end())),
+                };
+                Ok(self)
+            }
+        }
+    }
+
+    fn create_temp_cg_dir(&mut self, parent: &Path) -> std::result::Result<(), Error> {
+        let tmp_dir = Builder::new()
+            .prefix("cgmt-")
+            .rand_bytes(6)
+            .tempdir_in(parent)
+            .map_err(Error::io(format!(
+                "Can't create tempdir in folder '{}'",
+                parent.display()
+            )))?
+            .into_path();
+        self.temp_cg_dir = Some(tmp_dir);
+        Ok(())
+    }
+
+    /// Create the --persist-cgroup in `base` unless it exists already.
+    fn open_persist_cgroup(&mut self, base: &Path) -> std::result::Result<&mut Self, Error> {
+        let Some(name) = &self.options.persist_cgroup else {
+            return Ok(self);
+        };
+        let dir = base.join(name);
+        if !dir.is_dir() {
+            if self.options.report {
+                return Err(Error::BadCgroup(format!(
+                    "Persistent cgroup {} doesn't exist",
+                    dir.display()
+                )));
+            }
+            fs::create_dir(&dir)
+                .map_err(Error::io(format!("Can't make directory {}", dir.display())))?;
+        }
+        self.persist_dir = Some(dir);
+        Ok(self)
+    }
+
+    /// Print what accumulated in the --persist-cgroup, then remove it.
+    fn report(&mut self) -> std::result::Result<(), Error> {
+        let persist_dir = self.persist_dir.clone().unwrap();
+        let inspection = Inspection::read(&persist_dir)?;
+        self.emit(&self.format_opts(), |format, opts| {
+            output::render_inspection(&inspection, format, opts)
+        })?;
+        // the leaf is only there if a command has run in it
+        let leaf_dir = persist_dir.join("leaf");
+        for dir in [&leaf_dir, &persist_dir] {
+            if dir.exists() {
+                fs::remove_dir(dir).map_err(Error::io(format!(
+                    "Can't remove directory {}",
+                    dir.display()
+                )))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `own_cg` is what /proc/self/cgroup says, without a .service. Only
+    /// returns if re-running isn't possible.
+    fn reexec_with_systemd_run(&self, own_cg: &str) -> Error {
+        if self.options.disable_systemd_run {
+            return Error::NoServiceCgroup(own_cg.to_owned());
+        }
+        if self.options.verbose {
+            eprintln!(
+                "/proc/self/cgroup is {own_cg}, which isn't below a .service, and -Z isn't \
+                 given: re-running in a transient scope via systemd-run --user --scope"
+            );
+        }
+        let args: Vec<String> = std::env::args().collect();
+        let mut systemd = Command::new("systemd-run");
+        systemd
+            .arg("--user")
+            .arg("--scope")
+            .arg("--quiet")
+            .arg(args[0].as_str())
+            .arg("-Z");
+        for arg in args.iter().skip(1) {
+            systemd.arg(arg);
+        }
+        Error::SystemdRun(systemd.exec())
+    }
+
+    /// The cgroup the leaf is created in.
+    fn parent_cg_dir(&self) -> std::result::Result<PathBuf, Error> {
+        if let Some(persist_dir) = &self.persist_dir {
+            Ok(persist_dir.clone())
+        } else if let Some(temp_cg_dir) = &self.temp_cg_dir {
+            Ok(temp_cg_dir.clone())
+        } else if let Some(cg_dir) = &self.options.cg_dir {
+            Ok(PathBuf::from(cg_dir))
+        } else {
+            Err(Error::BadCgroup(
+                "No cgroup to create the leaf in - cf. -c option".to_owned(),
+            ))
+        }
+    }
+
+    fn setup_cgroup(&mut self) -> std::result::Result<&mut Self, Error> {
+        let cg_dir = self.parent_cg_dir()?;
+        read_dir(&cg_dir).map_err(Error::io(format!(
+            "Can't open directory {}",
+            cg_dir.display()
+        )))?;
+
+        // --attach moves the process into the fresh cgroup itself, so its
+        // subtree_control is never written while it's populated
+        if self.options.attach.is_some() {
+            check_controller(&cg_dir, "memory")?;
+            if self.options.cpu_max.is_some() {
+                check_controller(&cg_dir, "cpu")?;
+            }
+            if self.options.cpuset.is_some() {
+                check_controller(&cg_dir, "cpuset")?;
+            }
+            self.leaf_dir = Some(cg_dir);
+            self.apply_limits()?;
+            return Ok(self);
+        }
+
+        // otherwise, without the nested setup we can't add a process to the parent cgroup
+        // because we also need to write its cgroup.subtree_control file Cgroup v2
+        // disallows doing both (yields EBUSY) - cf. https://unix.stackexchange.com/a/713343/1131
+        let leaf_dir = cg_dir.join("leaf");
+        // a --persist-cgroup keeps its leaf from the previous invocation
+        if self.persist_dir.is_none() || !leaf_dir.exists() {
+            match fs::create_dir(&leaf_dir) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    self.replace_stale_leaf(&leaf_dir)?
+                }
+                res => res.map_err(Error::io(format!(
+                    "Can't make directory {}",
+                    leaf_dir.display()
+                )))?,
+            }
+        }
+        self.leaf_dir = Some(leaf_dir);
+
+        require_controller(&cg_dir, "memory")?;
+        if self.options.cpu_max.is_some() {
+            require_controller(&cg_dir, "cpu")?;
+        }
+        if self.options.cpuset.is_some() {
+            require_controller(&cg_dir, "cpuset")?;
+        }
+        // only for pids.peak, which is left out if the controller isn't
+        // available
+        let controllers = read_cg_value(&cg_dir.join("cgroup.controllers")).unwrap_or_default();
+        if has_controller(&controllers, "pids") {
+            let _ = enable_controller(&cg_dir.join("cgroup.subtree_control"), "pids");
+        }
+
+        self.apply_limits()?;
+        Ok(self)
+    }
+
+    /// Write --memory-max, --cpu-max and --cpuset to the leaf, which
+    /// requires their controllers being enabled in its parent.
+    fn apply_limits(&self) -> std::result::Result<(), Error> {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        if let Some(max) = self.options.memory_max {
+            let max_file = leaf_dir.join("memory.max");
+            fs::write(&max_file, max.to_string()).map_err(Error::io(format!(
+                "Write to file {} failed",
+                max_file.display()
+            )))?;
+        }
+        if let Some((quota, period)) = self.options.cpu_max {
+            let max_file = leaf_dir.join("cpu.max");
+            let quota = quota.map_or("max".to_owned(), |quota| quota.to_string());
+            fs::write(&max_file, format!("{quota} {period}")).map_err(Error::io(format!(
+                "Write to file {} failed",
+                max_file.display()
+            )))?;
+        }
+        if let Some(cpus) = &self.options.cpuset {
+            let cpus_file = leaf_dir.join("cpuset.cpus");
+            fs::write(&cpus_file, cpus).map_err(Error::io(format!(
+                "Write to file {} failed",
+                cpus_file.display()
+            )))?;
+        }
+        Ok(())
+    }
+
+    /// Recreate a leaf left over by a run that was killed before it could
+    /// clean up. If processes still run in it, only with --force, which
+    /// kills them.
+    fn replace_stale_leaf(&self, leaf_dir: &Path) -> std::result::Result<(), Error> {
+        let procs = read_procs(leaf_dir)?;
+        if !procs.is_empty() {
+            if !self.options.force {
+                return Err(Error::BadCgroup(format!(
+                    "Stale {} still contains {} process(es) - cf. --force option",
+                    leaf_dir.display(),
+                    procs.len()
+                )));
+            }
+            kill_cgroup(leaf_dir);
+        }
+        // the killed processes take a moment to leave the cgroup
+        let deadline = Instant::now() + self.options.kill_after;
+        while let Err(err) = fs::remove_dir(leaf_dir) {
+            if Instant::now() >= deadline {
+                return Err(Error::Io {
+                    what: format!("Can't remove stale {}", leaf_dir.display()),
+                    err,
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        fs::create_dir(leaf_dir).map_err(Error::io(format!(
+            "Can't make directory {}",
+            leaf_dir.display()
+        )))
+    }
+
+    /// Replace the leaf with a fresh one, e.g. to run the command again
+    /// without the old peak and counters.
+    fn reset_leaf(&mut self) -> std::result::Result<&mut Self, Error> {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        fs::remove_dir(leaf_dir).map_err(Error::io(format!(
+            "Can't remove directory {}",
+            leaf_dir.display()
+        )))?;
+        fs::create_dir(leaf_dir).map_err(Error::io(format!(
+            "Can't make directory {}",
+            leaf_dir.display()
+        )))?;
+        self.apply_limits()?;
+        Ok(self)
+    }
+
+    fn check_empty(&mut self) -> std::result::Result<&mut Self, Error> {
+        if !self.options.require_empty {
+            return Ok(self);
+        }
+        for dir in [self.parent_cg_dir()?, self.leaf_dir.clone().unwrap()] {
+            let pids = read_procs(&dir)?;
+            if !pids.is_empty() {
+                return Err(Error::NotEmpty { dir, pids });
+            }
+        }
+        Ok(self)
+    }
+
+    fn check_peak_support(&mut self) -> std::result::Result<&mut Self, Error> {
+        if self.options.fail_over.is_some()
+            && self.options.threshold_metric == ThresholdMetric::CgSwap
+        {
+            let swap_peak_file = self.leaf_dir.as_ref().unwrap().join("memory.swap.peak");
+            if !swap_peak_file.exists() {
+                return Err(Error::NoPeak {
+                    file: swap_peak_file,
+                    requires: "swap accounting",
+                    hint: "--threshold-metric option",
+                });
+            }
+        }
+        if self.options.allow_no_peak {
+            return Ok(self);
+        }
+        // fail before the command runs rather than after, when memory.peak is read
+        let peak_file = self.leaf_dir.as_ref().unwrap().join("memory.peak");
+        if !peak_file.exists() {
+            return Err(Error::NoPeak {
+                file: peak_file,
+                requires: "Kernel 5.19 or later",
+                hint: "--allow-no-peak and --sample-interval options",
+            });
+        }
+        Ok(self)
+    }
+
+    fn format_opts(&self) -> FormatOpts {
+        FormatOpts {
+            unit: self.options.unit,
+            time_unit: self.options.time_unit,
+            labels: self.options.labels.clone(),
+            delim: self.options.delim,
+            record_sep: self.options.record_sep,
+            emit_schema: self.options.emit_schema,
+            show_limits: self.options.show_limits,
+            cgroup_stat: self.options.cgroup_stat,
+            cpu_max: self.options.cpu_max.is_some(),
+            subtract_baseline: self.options.subtract_baseline,
+            sample_fds: self.options.sample_fds,
+            anon_peak: self.options.metrics.contains(&Metric::AnonPeak),
+            show_exe: self.options.show_exe,
+            oneline: self.options.oneline,
+            run_id: self.run_id.clone(),
+            env: self.env.clone(),
+            show_run_id: self.options.show_run_id,
+            no_cgroup: self.options.no_cgroup,
+            color: match self.options.color {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => {
+                    io::stdout().is_terminal()
+                        && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                }
+            },
+        }
+    }
+
+    /// SIGTERM everything in the leaf and SIGKILL what's still there after
+    /// --kill-after, returns how many processes had to be killed that way.
+    fn terminate(&self) -> std::result::Result<usize, Error> {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        for pid in read_procs(leaf_dir)? {
+            let _ = signal::kill(Pid::from_raw(pid), signal::Signal::SIGTERM);
+        }
+        let deadline = Instant::now() + self.options.kill_after;
+        while !read_procs(leaf_dir)?.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let survivors = read_procs(leaf_dir)?;
+        if !survivors.is_empty() {
+            kill_cgroup(leaf_dir);
+        }
+        Ok(survivors.len())
+    }
+
+    /// Probe the leaf a measurement would run in for the files the
+    /// metrics are read from.
+    fn print_capabilities(&self) {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        let files = [
+            "memory.peak",
+            "memory.swap.peak",
+            "memory.events",
+            "memory.stat",
+            "pids.peak",
+            "io.stat",
+            "memory.pressure",
+            "cpu.pressure",
+            "io.pressure",
+        ];
+        // the throttling fields are only there with the cpu controller enabled
+        let cpu_stat = read_keyed(&leaf_dir.join("cpu.stat")).unwrap_or_default();
+        let fields = [
+            "usage_usec",
+            "user_usec",
+            "system_usec",
+            "nr_throttled",
+            "throttled_usec",
+        ];
+        let capabilities = Capabilities {
+            files: files
+                .map(|file| (file, leaf_dir.join(file).exists()))
+                .to_vec(),
+            cpu_stat: fields
+                .map(|field| (field, cpu_stat.contains_key(field)))
+                .to_vec(),
+        };
+        print!(
+            "{}",
+            output::render_capabilities(
+                &capabilities,
+                self.options.stdout_format,
+                &self.format_opts()
+            )
+        );
+    }
+
+    /// Wait for processes which outlived the command, e.g. daemons it
+    /// forked, to exit, too. With --linger only that long, and it's no
+    /// surprise if they're still running then.
+    fn wait_all(&self) -> std::result::Result<Duration, Error> {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        let start = Instant::now();
+        loop {
+            let procs = read_procs(leaf_dir)?;
+            // check_deadline() kills them
+            if procs.is_empty() || self.remaining() == Some(Duration::ZERO) {
+                break;
+            }
+            if let Some(linger) = self.options.linger {
+                if start.elapsed() >= linger {
+                    break;
+                }
+            } else if start.elapsed() >= self.options.wait_all_timeout {
+                self.warn(&format!(
+                    "{} process(es) still running after --wait-all-timeout",
+                    procs.len()
+                ));
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(start.elapsed())
+    }
+
+    /// Time left until --deadline.
+    fn remaining(&self) -> Option<Duration> {
+        self.deadline_at
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Fail with Error::Deadline if --deadline passed during `phase`,
+    /// after killing what's left in the leaf.
+    fn check_deadline(&mut self, phase: &'static str) -> std::result::Result<(), Error> {
+        if self.remaining() != Some(Duration::ZERO) {
+            return Ok(());
+        }
+        if let Some(leaf_dir) = self.leaf_dir.clone() {
+            // failing anyway, an unreadable leaf is left to the cleanup
+            let running = |leaf_dir: &Path| read_procs(leaf_dir).is_ok_and(|p| !p.is_empty());
+            if running(&leaf_dir) {
+                kill_cgroup(&leaf_dir);
+                // give the killed processes a moment to leave the leaf
+                let deadline = Instant::now() + self.options.kill_after;
+                while running(&leaf_dir) && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+        Err(Error::Deadline {
+            deadline: self.options.deadline.unwrap(),
+            phase,
+        })
+    }
+
+    /// Report a measurement that's questionable but not wrong, with
+    /// --strict it fails the run.
+    fn warn(&self, msg: &str) {
+        if self.options.strict {
+            eprintln!("error: {msg} - cf. --strict option");
+            self.warned.set(true);
+        } else {
+            eprintln!("warning: {msg}");
+        }
+    }
+
+    fn cross_check(&self, result: &Result) {
+        let (Some(peak), Some(sampled)) = (result.cg_rss_highwater, result.sampled_peak) else {
+            self.warn("--cross-check needs both memory.peak and memory.current samples");
+            return;
+        };
+        let divergence = (peak - sampled).abs() as f64 * 100.0 / peak.max(1) as f64;
+        if divergence > self.options.cross_check_tolerance {
+            self.warn(&format!(
+                "memory.peak ({peak}) and sampled memory.current ({sampled}) diverge by {divergence:.1}%"
+            ));
+        }
+    }
+
+    /// `progress` gets the memory.current samples, cf. run_with_progress().
+    fn execute(&self, progress: Option<Progress>) -> std::result::Result<Result, Error> {
+        let Some(Action::Run(argv)) = &self.options.action else {
+            unreachable!()
+        };
+        let argv = if self.options.login_shell {
+            let shell = std::env::var("SHELL").unwrap_or("/bin/sh".to_owned());
+            let script: Vec<String> = argv.iter().map(|arg| shell_quote(arg)).collect();
+            vec![shell, "-lc".to_owned(), script.join(" ")]
+        } else {
+            argv.clone()
+        };
+        // before the clone, the child execs right away
+        let exe = self
+            .options
+            .show_exe
+            .then(|| resolve_executable(&argv[0]))
+            .flatten();
+        Ok(Result {
+            exe,
+            ..self.execute_argv(&argv, self.options.argv0.as_deref(), progress)?
+        })
+    }
+
+    /// Run each shell command line in a leaf of its own, up to `jobs` at a
+    /// time, and print the results in the order the commands finish.
+    fn run_parallel(&self, jobs: u32, commands: &[String]) -> std::result::Result<(), Error> {
+        let parent = self.parent_cg_dir()?;
+        let mut running = HashMap::new();
+        let res = self.run_jobs(&parent, jobs, commands, &mut running);
+        if res.is_err() {
+            // otherwise they'd outlive us, in leaves that can't be removed
+            for (pid, (job, _)) in running {
+                let leaf_dir = job.leaf_dir.as_ref().unwrap();
+                kill_cgroup(leaf_dir);
+                let _ = nix::sys::wait::waitpid(
+                    Pid::from_raw(pid),
+                    Some(nix::sys::wait::WaitPidFlag::__WALL),
+                );
+                // the killed descendants take a moment to leave the leaf
+                let deadline = Instant::now() + self.options.kill_after;
+                while read_procs(leaf_dir).is_ok_and(|procs| !procs.is_empty())
+                    && Instant::now() < deadline
+                {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+        res
+    }
+
+    /// The loop of run_parallel(), the jobs started but not yet reaped are
+    /// left in `running` when it fails.
+    fn run_jobs(
+        &self,
+        parent: &Path,
+        jobs: u32,
+        commands: &[String],
+        running: &mut HashMap<libc::pid_t, (Session, Instant)>,
+    ) -> std::result::Result<(), Error> {
+        let mut pending = commands.iter().enumerate();
+        let mut first = true;
+        loop {
+            while running.len() < jobs as usize {
+                let Some((index, command)) = pending.next() else {
+                    break;
+                };
+                let job = self.job(parent, index)?;
+                let child = job.spawn(
+                    &["/bin/sh".to_owned(), "-c".to_owned(), command.clone()],
+                    None,
+                )?;
+                let _ = nix::unistd::close(child.pidfd);
+                if let Some(err) = &child.exec_error {
+                    eprintln!("{err}");
+                }
+                running.insert(child.pid, (job, child.started));
+            }
+            if running.is_empty() {
+                break;
+            }
+
+            let mut status: i32 = 0;
+            let mut usg = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+            let (pid, usg) = unsafe {
+                let r = libc::wait4(-1, &mut status, libc::__WALL, usg.as_mut_ptr());
+                if r < 0 {
+                    return Err(Error::Io {
+                        what: "wait4 failed".to_owned(),
+                        err: io::Error::last_os_error(),
+                    });
+                }
+                (r, usg.assume_init())
+            };
+            let Some((mut job, started)) = running.remove(&pid) else {
+                continue;
+            };
+            let result = job.finish(Result::from_wait(started, status, usg), None)?;
+            // all the results go into one --output
+            job.options.append = self.options.append || !first;
+            first = false;
+            job.emit(&job.format_opts(), |format, opts| {
+                output::render(&result, format, opts)
+            })?;
+            if job.warned.get() {
+                self.warned.set(true);
+            }
+            // removes its leaf
+            job.succeeded = true;
+        }
+        Ok(())
+    }
+
+    /// A copy of the options for the `index`th parallel command, in a new
+    /// leaf that's removed when it's dropped. A stale leaf-N of an earlier
+    /// invocation is replaced like the leaf of a single run.
+    fn job(&self, parent: &Path, index: usize) -> std::result::Result<Session, Error> {
+        let leaf_dir = parent.join(format!("leaf-{index}"));
+        match fs::create_dir(&leaf_dir) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                self.replace_stale_leaf(&leaf_dir)?
+            }
+            res => res.map_err(Error::io(format!(
+                "Can't make directory {}",
+                leaf_dir.display()
+            )))?,
+        }
+        let mut job = self.clone();
+        job.temp_cg_dir = None;
+        job.persist_dir = None;
+        job.leaf_dir = Some(leaf_dir);
+        job.options
+            .labels
+            .push(("job".to_owned(), index.to_string()));
+        job.apply_limits()?;
+        Ok(job)
+    }
+
+    /// Measure a trivial command in a fresh leaf, so that the memory the
+    /// kernel charges to any cgroup can be told apart from the command's.
+    /// It's cloned straight into the leaf, none of the options that shape
+    /// the measured command apply and nothing samples it.
+    fn measure_baseline(&mut self) -> std::result::Result<i64, Error> {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        let fd = fcntl::open(
+            leaf_dir,
+            fcntl::OFlag::O_RDONLY | fcntl::OFlag::O_DIRECTORY,
+            Mode::empty(),
+        )
+        .map_err(|errno| Error::Io {
+            what: format!("Can't open directory {}", leaf_dir.display()),
+            err: errno.into(),
+        })?;
+        let mut clone = Clone3::default();
+        clone
+            .flag_into_cgroup(&fd)
+            .flag_vfork()
+            .exit_signal(libc::SIGCHLD as u64);
+        let clone_result = unsafe { clone.call() };
+        let _ = nix::unistd::close(fd);
+        match clone_result {
+            Ok(0) => {
+                let _ = Command::new("/bin/true").exec();
+                exit(127);
+            }
+            Ok(pid) => {
+                nix::sys::wait::waitpid(Pid::from_raw(pid), None).map_err(|errno| Error::Io {
+                    what: "waitpid failed".to_owned(),
+                    err: errno.into(),
+                })?;
+            }
+            Err(err) => {
+                return Err(Error::Io {
+                    what: "clone3 failed".to_owned(),
+                    err: io::Error::from_raw_os_error(err.0),
+                })
+            }
+        }
+        let baseline = read_cg_number(&leaf_dir.join("memory.peak")).unwrap_or_default();
+        self.reset_leaf()?;
+        Ok(baseline)
+    }
+
+    /// `argv0` replaces `args[0]` as the zeroth argument.
+    fn execute_argv(
+        &self,
+        args: &[String],
+        argv0: Option<&str>,
+        progress: Option<Progress>,
+    ) -> std::result::Result<Result, Error> {
+        let events_before = self.events_before();
+        let child = self.spawn(args, argv0)?;
+        let sampler = self.start_sampler(child.pid, child.started, progress);
+        let ready = self
+            .options
+            .ready_cmd
+            .as_ref()
+            .map(|cmd| self.wait_ready(&child, cmd))
+            .transpose()?
+            .flatten();
+        let timeout = self.wait_timeout(child.pidfd, child.started)?;
+
+        let mut status: i32 = 0;
+        let mut usg = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+        let usg = unsafe {
+            // __WALL also reaps children with an --exit-signal other than SIGCHLD
+            let r = libc::wait4(child.pid, &mut status, libc::__WALL, usg.as_mut_ptr());
+            if r < 0 {
+                return Err(Error::Io {
+                    what: "wait4 failed".to_owned(),
+                    err: io::Error::last_os_error(),
+                });
+            }
+            usg.assume_init()
+        };
+        let _ = nix::unistd::close(child.pidfd);
+
+        let result = Result {
+            timeout,
+            ready,
+            exec_error: child.exec_error,
+            events_before,
+            ..Result::from_wait(child.started, status, usg)
+        };
+        self.finish(result, sampler)
+    }
+
+    /// Clone a child into the leaf, or without one with --no-cgroup, that
+    /// execs `args`.
+    fn spawn(&self, args: &[String], argv0: Option<&str>) -> std::result::Result<Child, Error> {
+        let fd = self
+            .leaf_dir
+            .as_ref()
+            .map(|leaf_dir| {
+                fcntl::open(
+                    leaf_dir,
+                    fcntl::OFlag::O_RDONLY | fcntl::OFlag::O_DIRECTORY,
+                    Mode::empty(),
+                )
+                .map_err(|errno| Error::Io {
+                    what: format!("Can't open directory {}", leaf_dir.display()),
+                    err: errno.into(),
+                })
+            })
+            .transpose()?;
+
+        // opened here so that a bad path fails before anything is cloned
+        let open = |path: &PathBuf, file: io::Result<File>| {
+            file.map_err(Error::io(format!("Can't open file {}", path.display())))
+        };
+        let stdin = self
+            .options
+            .stdin
+            .as_ref()
+            .map(|path| open(path, File::open(path)))
+            .transpose()?;
+        let stdout = self
+            .options
+            .stdout
+            .as_ref()
+            .map(|path| open(path, File::create(path)))
+            .transpose()?;
+        let stderr = self
+            .options
+            .stderr
+            .as_ref()
+            .map(|path| open(path, File::create(path)))
+            .transpose()?;
+
+        // Dir
+        let mut pidfd = -1;
+        let mut clone = Clone3::default();
+        clone
+            .flag_pidfd(&mut pidfd)
+            .exit_signal(self.options.exit_signal as u64);
+        if let Some(fd) = &fd {
+            clone.flag_into_cgroup(fd);
+        }
+        if !self.options.no_vfork {
+            clone.flag_vfork();
+        }
+        if self.options.pid_namespace {
+            // wait4() still works as usual with the PID in our namespace
+            clone.flag_newpid();
+        }
+
+        // closed by a successful exec, otherwise the child reports the error
+        // through it
+        let (err_rx, err_tx) =
+            nix::unistd::pipe2(fcntl::OFlag::O_CLOEXEC).map_err(|errno| Error::Io {
+                what: "Can't create pipe".to_owned(),
+                err: errno.into(),
+            })?;
+
+        let t_start = Instant::now();
+
+        let clone_result = unsafe { clone.call() };
+        let child_pid = clone_result.map_err(|err| {
+            let _ = nix::unistd::close(err_rx);
+            let _ = nix::unistd::close(err_tx);
+            Error::Io {
+                what: if self.options.pid_namespace && err.0 == libc::EPERM {
+                    "clone3 failed (--pid-namespace requires CAP_SYS_ADMIN)".to_owned()
+                } else {
+                    "clone3 failed".to_owned()
+                },
+                err: io::Error::from_raw_os_error(err.0),
+            }
+        })?;
+        match child_pid {
+            0 => {
+                // child
+                assert!(!args.is_empty());
+                let mut sub_command = Command::new(args[0].as_str());
+                for arg in args.iter().skip(1) {
+                    sub_command.arg(arg);
+                }
+                if let Some(argv0) = argv0 {
+                    sub_command.arg0(argv0);
+                }
+                let rlimits = self.options.rlimits.clone();
+                let sigactions = self.child_sigactions.clone();
+                unsafe {
+                    // right before the exec, so that e.g. a tight AS doesn't
+                    // hit the preparations
+                    sub_command.pre_exec(move || {
+                        // an ignored signal stays ignored across the exec
+                        for (sig, sa) in &sigactions {
+                            signal::sigaction(*sig, sa)?;
+                        }
+                        for &(resource, limit) in &rlimits {
+                            // like prlimit(1) with a single value
+                            resource::setrlimit(resource, limit, limit)?;
+                        }
+                        Ok(())
+                    });
+                }
+                if let Some(stdin) = stdin {
+                    sub_command.stdin(stdin);
+                }
+                if let Some(stdout) = stdout {
+                    sub_command.stdout(stdout);
+                }
+                if let Some(stderr) = stderr {
+                    sub_command.stderr(stderr);
+                }
+                let err = sub_command.exec();
+                let msg = format!("can't exec '{}': {err}", args[0]);
+                let _ = nix::unistd::write(err_tx, msg.as_bytes());
+                exit(127);
+            }
+            child_pid => {
+                // parent
+                if let Some(fd) = fd {
+                    let _ = nix::unistd::close(fd);
+                }
+                let _ = nix::unistd::close(err_tx);
+                // without vfork, this waits for the exec
+                let mut exec_error = String::new();
+                let _ = unsafe { File::from_raw_fd(err_rx) }.read_to_string(&mut exec_error);
+                Ok(Child {
+                    pid: child_pid,
+                    pidfd,
+                    started: t_start,
+                    exec_error: (!exec_error.is_empty()).then_some(exec_error),
+                })
+            }
+        }
+    }
+
+    /// Move the running process `pid` into the leaf and measure it until it
+    /// exits. Only what it uses from then on is charged to the leaf, and as
+    /// it isn't our child there's no rusage, so the times come from the
+    /// leaf's cpu.stat.
+    fn attach(&self, pid: libc::pid_t) -> std::result::Result<Result, Error> {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if pidfd < 0 {
+            return Err(Error::Io {
+                what: format!("Can't attach to PID {pid}"),
+                err: io::Error::last_os_error(),
+            });
+        }
+        let pidfd = pidfd as RawFd;
+
+        let events_before = self.events_before();
+        let t_start = Instant::now();
+        let procs_file = leaf_dir.join("cgroup.procs");
+        if let Err(err) = fs::write(&procs_file, pid.to_string()) {
+            let _ = nix::unistd::close(pidfd);
+            return Err(Error::Io {
+                what: format!("Can't move PID {pid} into {}", procs_file.display()),
+                err,
+            });
+        }
+
+        let sampler = self.start_sampler(pid, t_start, None);
+        let waited = self
+            .wait_timeout(pidfd, t_start)
+            .and_then(|timeout| wait_pidfd(pidfd, None).map(|_| timeout));
+        let _ = nix::unistd::close(pidfd);
+        let timeout = waited?;
+
+        let cpu_stat = read_keyed(&leaf_dir.join("cpu.stat")).unwrap_or_default();
+        let usec =
+            |key: &str| Duration::from_micros(cpu_stat.get(key).copied().unwrap_or_default());
+        let result = Result {
+            child_user: usec("user_usec"),
+            child_sys: usec("system_usec"),
+            child_wall: t_start.elapsed(),
+            timeout,
+            events_before,
+            ..Default::default()
+        };
+        self.finish(result, sampler)
+    }
+
+    fn start_sampler(
+        &self,
+        pid: libc::pid_t,
+        started: Instant,
+        progress: Option<Progress>,
+    ) -> Option<Sampler> {
+        let sample_interval = match self.options.sample_interval {
+            None if self.options.cross_check
+                || self.options.hang_timeout.is_some()
+                || self.options.follow
+                || self.options.sample_fds
+                || !self.options.metrics.is_empty()
+                || self.options.abort_swap_above.is_some()
+                || self.options.proc_status
+                || self.options.trace_events.is_some()
+                || self.options.trace_out.is_some() =>
+            {
+                Some(DEFAULT_SAMPLE_INTERVAL)
+            }
+            interval => interval,
+        };
+        sample_interval.map(|interval| {
+            let leaf_dir = self.leaf_dir.as_ref().unwrap();
+            // the command already runs, don't abort over the traces
+            let open = |path: &PathBuf| {
+                File::options()
+                    .create(true)
+                    .append(self.options.append)
+                    .write(true)
+                    .truncate(!self.options.append)
+                    .open(path)
+                    .map_err(|err| self.warn(&format!("can't open {}: {err}", path.display())))
+                    .ok()
+            };
+            let events = self.options.trace_events.as_ref().and_then(open);
+            let trace = self.options.trace_out.as_ref().and_then(open).map(|file| {
+                let mut trace = BufWriter::new(file);
+                // appended runs share the header
+                if trace.get_ref().metadata().is_ok_and(|meta| meta.len() == 0) {
+                    let _ = writeln!(trace, "seconds,bytes");
+                }
+                trace
+            });
+            Sampler::start(
+                leaf_dir.clone(),
+                started,
+                interval,
+                Probes {
+                    hang_timeout: self.options.hang_timeout,
+                    follow: self.options.follow,
+                    unit: self.options.unit,
+                    count_fds: self.options.sample_fds,
+                    anon: self.options.metrics.contains(&Metric::AnonPeak),
+                    max_swap: self.options.abort_swap_above,
+                    proc_status: self.options.proc_status.then_some(pid),
+                    max_overhead: self
+                        .options
+                        .max_sample_overhead
+                        .map(|percent| percent / 100.0),
+                },
+                events.map(EventTrace::new),
+                trace,
+                progress,
+            )
+        })
+    }
+
+    /// Run the --ready-cmd until it succeeds, returns None if the command
+    /// exits or --ready-timeout passes first. Polling stops at --timeout
+    /// and --deadline as well, all counted from the start of the command,
+    /// for wait_timeout() to take over.
+    fn wait_ready(&self, child: &Child, cmd: &str) -> std::result::Result<Option<Ready>, Error> {
+        let leaf_dir = self.leaf_dir.as_ref().unwrap();
+        let ready_deadline = child.started + self.options.ready_timeout;
+        let deadline = [
+            self.options.timeout.map(|timeout| child.started + timeout),
+            self.deadline_at,
+        ]
+        .into_iter()
+        .flatten()
+        .fold(ready_deadline, Instant::min);
+        loop {
+            let ready = Command::new("/bin/sh")
+                .arg("-c")
+                .arg(cmd)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+            if ready {
+                let ready = Ready {
+                    after: child.started.elapsed(),
+                    mem: read_cg_number(&leaf_dir.join("memory.peak")),
+                    stopped: self.options.stop_after_ready,
+                };
+                if self.options.stop_after_ready {
+                    self.terminate()?;
+                }
+                return Ok(Some(ready));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                if deadline == ready_deadline {
+                    self.warn(&format!(
+                        "--ready-cmd didn't succeed within {:?}",
+                        self.options.ready_timeout
+                    ));
+                }
+                return Ok(None);
+            }
+            // doubles as the pause between the attempts
+            if wait_pidfd(child.pidfd, Some(READY_POLL_INTERVAL.min(left)))? {
+                self.warn("the command exited before --ready-cmd succeeded");
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Wait until --timeout after `started` for the process behind `pidfd`,
+    /// then terminate the leaf.
+    fn wait_timeout(
+        &self,
+        pidfd: RawFd,
+        started: Instant,
+    ) -> std::result::Result<Option<Timeout>, Error> {
+        let timeout = self
+            .options
+            .timeout
+            .map(|timeout| timeout.saturating_sub(started.elapsed()));
+        let timeout = match (timeout, self.remaining()) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        };
+        match timeout {
+            Some(timeout) if !wait_pidfd(pidfd, Some(timeout))? => Ok(Some(Timeout {
+                killed_hard: self.terminate()?,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// The leaf's memory.events, only needed for --fail-on-events: a
+    /// --persist-cgroup leaf counts from earlier runs.
+    fn events_before(&self) -> HashMap<String, u64> {
+        match &self.leaf_dir {
+            Some(leaf_dir) if !self.options.fail_on_events.is_empty() => {
+                read_keyed(&leaf_dir.join("memory.events")).unwrap_or_default()
+            }
+            _ => HashMap::new(),
+        }
+    }
+
+    /// The --fail-on-events counters that went up during the run of
+    /// `result`.
+    fn tripped_events(&self, result: &Result) -> Vec<(String, u64)> {
+        self.options
+            .fail_on_events
+            .iter()
+            .filter_map(|event| {
+                let name = event.name().to_owned();
+                let count =
+                    |events: &HashMap<String, u64>| events.get(&name).copied().unwrap_or_default();
+                let delta = count(&result.snapshot.memory_events)
+                    .saturating_sub(count(&result.events_before));
+                (delta > 0).then_some((name, delta))
+            })
+            .collect()
+    }
+
+    /// The --threshold-metric of `result` if it's above --fail-over.
+    fn fail_over(&self, result: &Result) -> Option<i64> {
+        let limit = self.options.fail_over? as i64;
+        let value = match self.options.threshold_metric {
+            ThresholdMetric::CgRss => result.cg_rss_highwater,
+            ThresholdMetric::ChildRss => Some(result.child_rss_highwater),
+            ThresholdMetric::PeakRss => Some(result.peak_rss),
+            ThresholdMetric::CgSwap => result.cg_swap_peak,
+        }?;
+        (value > limit).then_some(value)
+    }
+
+    /// Complete `result` with the leaf's figures once the measured process
+    /// has exited.
+    fn finish(
+        &self,
+        mut result: Result,
+        sampler: Option<Sampler>,
+    ) -> std::result::Result<Result, Error> {
+        let Some(leaf_dir) = self.leaf_dir.as_ref() else {
+            // --no-cgroup, the rusage is all there is
+            result.peak_rss = result.child_rss_highwater;
+            result.fail_over = self.fail_over(&result);
+            return Ok(result);
+        };
+        result.waited_all = (self.options.wait_all || self.options.linger.is_some())
+            .then(|| self.wait_all())
+            .transpose()?;
+        // read no matter how the command ended, for the state at its death
+        result.snapshot = Snapshot::read(leaf_dir);
+        let event = |key: &str| {
+            result
+                .snapshot
+                .memory_events
+                .get(key)
+                .copied()
+                .unwrap_or_default()
+        };
+        result.ooms = event("oom");
+        result.oom_kills = event("oom_kill");
+        let cpu = |key: &str| {
+            result
+                .snapshot
+                .cpu_stat
+                .get(key)
+                .map(|&usec| Duration::from_micros(usec))
+        };
+        result.cg_cpu_usage = cpu("usage_usec");
+        result.cg_cpu_user = cpu("user_usec");
+        result.cg_cpu_system = cpu("system_usec");
+        if self.options.cpu_max.is_some() {
+            result.nr_throttled = result.snapshot.cpu_stat.get("nr_throttled").copied();
+            result.throttled = cpu("throttled_usec");
+        }
+        let mut samples = sampler.map(Sampler::stop);
+
+        // read cg rss high
+        let peak_file = leaf_dir.join("memory.peak");
+        if !self.options.allow_no_peak || peak_file.exists() {
+            let buf = read_cg_value(&peak_file).map_err(Error::io(format!(
+                "Can't read {} (requires Kernel 5.19 or later)",
+                peak_file.display()
+            )))?;
+            result.cg_rss_highwater = Some(buf.parse().map_err(Error::invalid(format!(
+                "Can't parse {buf} in {}",
+                peak_file.display()
+            )))?);
+        }
+        result.cg_swap_peak = read_cg_number(&leaf_dir.join("memory.swap.peak"));
+        result.pids_peak = read_cg_number(&leaf_dir.join("pids.peak"));
+        if self.options.nested_breakdown {
+            result.parent_peak = leaf_dir
+                .parent()
+                .and_then(|parent| read_cg_number(&parent.join("memory.peak")));
+        }
+        if let Some(mut events) = samples.as_mut().and_then(|samples| samples.events.take()) {
+            // catch what happened between the last sample and the exit
+            events.update(&result.snapshot.memory_events, result.child_wall);
+        }
+        if let Some(samples) = samples {
+            result.sampled_peak = samples.max_current;
+            result.sampled_peak_at = samples.max_current_at;
+            result.hung = samples.hung;
+            result.swap_abort = samples.swap_abort.map(|v| v as i64);
+            result.sampled_fds = samples.max_fds;
+            result.proc_status = samples.proc_status;
+            result.sampled_anon_peak = samples.max_anon.map(|v| v as i64);
+            if self.options.max_sample_overhead.is_some() {
+                result.sample_interval_avg = samples.avg_interval;
+            }
+        }
+        if self.options.cross_check {
+            self.cross_check(&result);
+        }
+        result.peak_rss = result
+            .cg_rss_highwater
+            .map_or(result.child_rss_highwater, |cg| {
+                cg.max(result.child_rss_highwater)
+            });
+        result.fail_over = self.fail_over(&result);
+        result.tripped_events = self.tripped_events(&result);
+
+        if self.options.numa {
+            result.numa = read_numa_stat(&leaf_dir.join("memory.numa_stat")).ok();
+        }
+
+        if self.options.cgroup_stat {
+            result.cgroup_stat =
+                read_keyed(&leaf_dir.join("cgroup.stat"))
+                    .ok()
+                    .map(|stat| CgroupStat {
+                        nr_descendants: stat.get("nr_descendants").copied().unwrap_or_default(),
+                        nr_dying_descendants: stat
+                            .get("nr_dying_descendants")
+                            .copied()
+                            .unwrap_or_default(),
+                    });
+        }
+
+        if self.options.show_limits {
+            let base = Path::new(&self.options.cg_fs_dir);
+            result.limits = Some(Limits {
+                memory_max: effective_limit(leaf_dir, base, "memory.max")?,
+                memory_high: effective_limit(leaf_dir, base, "memory.high")?,
+                memory_swap_max: effective_limit(leaf_dir, base, "memory.swap.max")?,
+            });
+        }
+        Ok(result)
+    }
+}
+
+impl Session {
+    fn cleanup(&mut self) {
+        // --attach has no leaf of its own
+        if self.leaf_dir == self.temp_cg_dir {
+            self.leaf_dir.take();
+        }
+        if self.persist_dir.is_some() {
+            self.leaf_dir.take();
+            return;
+        }
+        let keep = if self.options.keep_cgroup {
+            Some("--keep-cgroup")
+        } else {
+            (self.options.no_cleanup_on_error && !self.succeeded).then_some("--no-cleanup-on-error")
+        };
+        if let Some(option) = keep {
+            for dir in [self.leaf_dir.take(), self.temp_cg_dir.take()]
+                .into_iter()
+                .flatten()
+            {
+                eprintln!("Keeping {} - cf. {option}", dir.display());
+            }
+            return;
+        }
+        let started = Instant::now();
+        let removing = self.leaf_dir.is_some() || self.temp_cg_dir.is_some();
+        if let Some(leaf_dir) = self.leaf_dir.take() {
+            if let Err(err) = fs::remove_dir(&leaf_dir) {
+                eprintln!("Failed to remove {}: {:?}", leaf_dir.display(), err);
+            }
+        }
+        if let Some(temp_cg_dir) = self.temp_cg_dir.take() {
+            if let Err(err) = fs::remove_dir(&temp_cg_dir) {
+                eprintln!("Failed to remove {}: {:?}", temp_cg_dir.display(), err);
+            }
+        }
+        if self.options.show_overhead && removing {
+            eprintln!("teardown_overhead: {:?}", started.elapsed());
+        }
+    }
+
+    /// The process-wide signal setup of the command line, left out by run()
+    /// as it would change the caller's.
+    fn set_signal_dispositions(&mut self) -> std::result::Result<(), Error> {
+        // otherwise, Ctrl+C/+] also kill cgmemtime before it has a chance printing its summary
+        let ignore = signal::SigAction::new(
+            signal::SigHandler::SigIgn,
+            signal::SaFlags::empty(),
+            signal::SigSet::empty(),
+        );
+        for sig in [signal::Signal::SIGINT, signal::Signal::SIGQUIT] {
+            let old = unsafe { signal::sigaction(sig, &ignore) }.map_err(|errno| Error::Io {
+                what: format!("Failed to ignore {sig}"),
+                err: errno.into(),
+            })?;
+            self.child_sigactions.push((sig, old));
+        }
+        if self.options.exit_signal != signal::Signal::SIGCHLD {
+            // a handler rather than SIG_IGN, so that the exec resets it
+            extern "C" fn noop(_: libc::c_int) {}
+            let sa = signal::SigAction::new(
+                signal::SigHandler::Handler(noop),
+                signal::SaFlags::SA_RESTART,
+                signal::SigSet::empty(),
+            );
+            unsafe { signal::sigaction(self.options.exit_signal, &sa) }.map_err(|errno| {
+                Error::Io {
+                    what: format!("Failed to handle {}", self.options.exit_signal),
+                    err: errno.into(),
+                }
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+impl Session {
+    /// Print the output to stdout and, with --output, to the file.
+    fn emit(
+        &self,
+        opts: &FormatOpts,
+        render: impl Fn(OutputFormat, &FormatOpts) -> String,
+    ) -> std::result::Result<(), Error> {
+        match self.options.stdout_format {
+            OutputFormat::Human => println!("{}", render(OutputFormat::Human, opts)),
+            format => print!("{}", render(format, opts)),
+        }
+        if let Some(path) = &self.options.output {
+            let mut file = File::options()
+                .create(true)
+                .append(self.options.append)
+                .write(true)
+                .truncate(!self.options.append)
+                .open(path)
+                .map_err(Error::io(format!("Can't open file {}", path.display())))?;
+            let file_opts = FormatOpts {
+                color: false,
+                ..opts.clone()
+            };
+            file.write_all(render(self.options.file_format, &file_opts).as_bytes())
+                .map_err(Error::io(format!(
+                    "Write to file {} failed",
+                    path.display()
+                )))?;
+        }
+        if let Some(path) = &self.options.socket {
+            let socket_opts = FormatOpts {
+                color: false,
+                ..opts.clone()
+            };
+            // a missing collector shouldn't fail the measurement
+            if let Err(err) = UnixStream::connect(path).and_then(|mut stream| {
+                stream.write_all(render(self.options.file_format, &socket_opts).as_bytes())
+            }) {
+                self.warn(&format!(
+                    "can't send the result to {}: {err}",
+                    path.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single measurement for run(), the library counterpart of a plain
+/// `cgmemtime [-m BASE] [-c CGROUP] [--memory-max SIZE] [--cpu-max Q/P]
+/// [--cpuset LIST] COMMAND...`.
+#[derive(Clone, Debug)]
+pub struct RunSpec {
+    /// the command and its arguments, executed without a shell
+    pub argv: Vec<String>,
+    /// cgroup v2 base, cf. -m
+    pub cg_fs_dir: String,
+    /// cgroup to create the leaf in, cf. -c
+    pub cg_dir: Option<String>,
+    /// memory.max of the leaf in bytes, cf. --memory-max
+    pub memory_max: Option<u64>,
+    /// cpu.max of the leaf as quota and period in microseconds, a None
+    /// quota is max, cf. --cpu-max
+    pub cpu_max: Option<(Option<u64>, u64)>,
+    /// cpuset.cpus of the leaf, e.g. 0-3,7, cf. --cpuset
+    pub cpuset: Option<String>,
+}
+
+impl Default for RunSpec {
+    fn default() -> RunSpec {
+        RunSpec {
+            argv: Vec::new(),
+            cg_fs_dir: "/sys/fs/cgroup".to_owned(),
+            cg_dir: None,
+            memory_max: None,
+            cpu_max: None,
+            cpuset: None,
+        }
+    }
+}
+
+impl RunSpec {
+    /// The options of the command line with the defaults for everything
+    /// the spec doesn't cover.
+    fn into_session(self) -> Session {
+        Session::new(Options {
+            cg_fs_dir: self.cg_fs_dir,
+            cg_dir: self.cg_dir,
+            memory_max: self.memory_max,
+            cpu_max: self.cpu_max,
+            cpuset: self.cpuset,
+            action: Some(Action::Run(self.argv)),
+            // re-executing would replace the caller's process
+            disable_systemd_run: true,
+            ..Options::default()
+        })
+    }
+}
+
+/// Measure a command in a fresh leaf cgroup, which is removed again
+/// afterwards.
+///
+/// A command that fails or is killed still yields Ok, cf.
+/// Result::failure(). The error is about the measurement itself, e.g. the
+/// cgroup couldn't be set up, or the command couldn't be executed at all.
+///
+/// Unlike the command line, run() leaves the signal dispositions alone,
+/// so a Ctrl+C also reaches the caller.
+pub fn run(spec: RunSpec) -> std::result::Result<Result, Error> {
+    run_spec(spec, None)
+}
+
+/// Like run(), but sample memory.current every `interval` while the
+/// command runs and pass each reading to `on_sample`, e.g. to show the
+/// progress of a long build. Returns once the command exited, with the
+/// sampled figures in the Result.
+///
+/// `on_sample` is called on a thread of its own rather than the caller's,
+/// hence Send, one sample at a time. A slow callback delays the next
+/// sample. Returning ControlFlow::Break stops the sampling but not the
+/// command, and a panic in the callback resurfaces in run_with_progress(),
+/// with the callback's payload, once the command exited.
+pub fn run_with_progress(
+    spec: RunSpec,
+    interval: Duration,
+    on_sample: impl FnMut(&Sample) -> ControlFlow<()> + Send + 'static,
+) -> std::result::Result<Result, Error> {
+    run_spec(spec, Some((interval, Box::new(on_sample))))
+}
+
+fn run_spec(
+    spec: RunSpec,
+    progress: Option<(Duration, Progress)>,
+) -> std::result::Result<Result, Error> {
+    if spec.argv.is_empty() {
+        return Err(Error::NoCommand);
+    }
+    let mut session = spec.into_session();
+    session
+        .check_cgroupfs()?
+        .check_cgroup_dir()?
+        .setup_cgroup()?
+        .check_peak_support()?;
+    let progress = progress.map(|(interval, on_sample)| {
+        // a zero interval would keep the sampler thread busy
+        session.options.sample_interval = Some(interval.max(Duration::from_millis(1)));
+        on_sample
+    });
+    let result = session.execute(progress)?;
+    if let Some(err) = result.exec_error {
+        return Err(Error::Exec(err));
+    }
+    session.succeeded = true;
+    Ok(result)
+}
+
+/// Do what the command line does with `options`: print the outcome on
+/// stdout and, with --output, to the file, and return the exit status of
+/// cgmemtime. Unlike run(), it sets up the signal dispositions of the
+/// command line, e.g. Ctrl+C is left to the command.
+pub fn invoke(options: Options) -> i32 {
+    let mut session = Session::new(options);
+    session.invoke().unwrap_or_else(|err| {
+        match session.options.stdout_format {
+            OutputFormat::Json => eprint!("{}", output::render_error(&err)),
+            _ => eprintln!("error: {err}"),
+        }
+        err.exit_code()
+    })
+}
+
+impl Session {
+    fn invoke(&mut self) -> std::result::Result<i32, Error> {
+        if self.options.include_env {
+            self.env = Some(Env::read()?);
+        }
+        if self.options.schema_version {
+            print!("{}", output::render_schema(&self.format_opts()));
+            return Ok(0);
+        }
+        if self.options.report {
+            self.check_cgroupfs()?.check_cgroup_dir()?.report()?;
+            self.succeeded = true;
+            return Ok(0);
+        }
+        if let Some(Action::Diff { old, new }) = &self.options.action {
+            let (old, new) = (read_result_file(old)?, read_result_file(new)?);
+            print!("{}", output::render_diff(&old, &new, &self.format_opts()));
+            self.succeeded = true;
+            return Ok(0);
+        }
+        if let Some(Action::Schema) = self.options.action {
+            print!("{}", output::render_json_schema(self.options.time_unit));
+            self.succeeded = true;
+            return Ok(0);
+        }
+        if let Some(Action::Inspect(path)) = &self.options.action {
+            let inspection = Inspection::read(path)?;
+            self.emit(&self.format_opts(), |format, opts| {
+                output::render_inspection(&inspection, format, opts)
+            })?;
+            self.succeeded = true;
+            return Ok(0);
+        }
+        let setup_started = Instant::now();
+        if !self.options.no_cgroup {
+            self.check_cgroupfs()?.check_cgroup_dir()?.setup_cgroup()?;
+        }
+        self.setup_overhead = setup_started.elapsed();
+        self.check_deadline("setup")?;
+        if let Some(Action::Capabilities) = self.options.action {
+            self.print_capabilities();
+            self.succeeded = true;
+            return Ok(0);
+        }
+        if !self.options.no_cgroup {
+            self.check_empty()?.check_peak_support()?;
+        }
+        self.set_signal_dispositions()?;
+        if let Some(Action::Parallel { jobs, mut commands }) = self.options.action.clone() {
+            if commands.is_empty() {
+                commands = io::stdin()
+                    .lines()
+                    .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                    .collect::<io::Result<_>>()
+                    .map_err(Error::io("Can't read stdin".to_owned()))?;
+            }
+            self.run_parallel(jobs, &commands)?;
+            let code = if self.warned.get() { 123 } else { 0 };
+            self.succeeded = code == 0;
+            return Ok(code);
+        }
+        let opts = self.format_opts();
+        let mut code = 0;
+        let mut prev: Option<Result> = None;
+        let baseline = match self.options.subtract_baseline {
+            true => Some(self.measure_baseline()?),
+            false => None,
+        };
+        self.check_deadline("the baseline run")?;
+        let runs = match self.options.until_stable {
+            Some(_) => self.options.stable_max_runs,
+            None => self.options.iterations,
+        };
+        let mut values = Vec::new();
+        for iteration in 0..runs {
+            // the runs in a --persist-cgroup accumulate
+            if iteration > 0 && self.persist_dir.is_none() && !self.options.no_cgroup {
+                self.reset_leaf()?;
+            }
+            let result = match self.options.attach {
+                Some(pid) => self.attach(pid),
+                None => self.execute(None),
+            };
+            let mut result = result?;
+            if self.options.retry_on_oom > 0 {
+                let mut attempts = 1;
+                while result.oom_kills > 0 && attempts <= self.options.retry_on_oom {
+                    result = self.reset_leaf()?.execute(None)?;
+                    attempts += 1;
+                }
+                result.attempts = Some(attempts);
+            }
+            if let Some(err) = &result.exec_error {
+                eprintln!("{err}");
+            }
+            result.baseline = baseline;
+            let quiet = self.options.summary_only_on_change
+                && prev
+                    .as_ref()
+                    .is_some_and(|prev| !result.changed_from(prev, self.options.change_threshold));
+            if !quiet {
+                self.emit(&opts, |format, opts| output::render(&result, format, opts))?;
+                // collect all the runs in --output
+                self.options.append = true;
+            }
+            // same as timeout(1), the last failing run wins
+            code = match &result.timeout {
+                Some(timeout) if timeout.killed_hard > 0 => 137,
+                Some(_) => 124,
+                None if result.hung => 122,
+                None if result.swap_abort.is_some() => 125,
+                None => match result.shell_status() {
+                    Some(status) => status,
+                    None if result.fail_over.is_some() => 117,
+                    None if !result.tripped_events.is_empty() => 114,
+                    None => code,
+                },
+            };
+            if let Some(metric) = self.options.until_stable {
+                values.push(metric.of(&result));
+            }
+            prev = Some(result);
+            self.check_deadline("the run")?;
+            if values.len() >= MIN_STABLE_RUNS && spread(&values).2 < self.options.stable_threshold
+            {
+                break;
+            }
+        }
+        if let Some(metric) = self.options.until_stable {
+            let (mean, stddev, rsd) = spread(&values);
+            eprintln!(
+                "runs: {} {} mean: {} stddev: {} ({rsd:.1}%)",
+                values.len(),
+                metric.name(),
+                metric.format(mean, &opts),
+                metric.format(stddev, &opts)
+            );
+            if rsd >= self.options.stable_threshold || values.len() < MIN_STABLE_RUNS {
+                self.warn(&format!(
+                    "{} didn't stabilize within {} runs - cf. --stable-max-runs",
+                    metric.name(),
+                    values.len()
+                ));
+            }
+        }
+        if self.options.self_usage {
+            print_self_usage(&opts)?;
+        }
+        if self.options.show_overhead {
+            eprintln!("setup_overhead: {:?}", self.setup_overhead);
+        }
+        if code == 0 && self.warned.get() {
+            code = 123;
+        }
+        self.succeeded = code == 0;
+        self.cleanup();
+        self.check_deadline("teardown")?;
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A session measuring `argv` with `options`.
+    fn session(argv: &[&str], options: Options) -> Session {
+        let argv = argv.iter().map(|arg| arg.to_string()).collect();
+        Session::new(Options {
+            action: Some(Action::Run(argv)),
+            ..options
+        })
+    }
+
+    #[test]
+    fn check_cgroup_dir_rejects_a_plain_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let cg_dir = Some(dir.to_owned());
+        let mut session = session(
+            &["true"],
+            Options {
+                cg_dir,
+                ..Options::default()
+            },
+        );
+        match session.check_cgroup_dir() {
+            Err(Error::BadCgroup(msg)) => {
+                assert_eq!(
+                    msg,
+                    format!("Directory {dir} isn't on a cgroup2 filesystem.")
+                )
+            }
+            res => panic!("expected BadCgroup, got {res:?}"),
+        }
+    }
+
+    #[test]
+    fn check_cgroup_dir_rejects_a_missing_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("foo");
+        let missing = missing.to_str().unwrap();
+        let cg_dir = Some(missing.to_owned());
+        let mut session = session(
+            &["true"],
+            Options {
+                cg_dir,
+                ..Options::default()
+            },
+        );
+        match session.check_cgroup_dir() {
+            Err(Error::BadCgroup(msg)) => {
+                assert_eq!(msg, format!("Directory {missing} does not exist."))
+            }
+            res => panic!("expected BadCgroup, got {res:?}"),
+        }
+    }
+
+    #[test]
+    fn no_parent_cg_dir_without_a_cgroup() {
+        let session = session(&["true"], Options::default());
+        match session.parent_cg_dir() {
+            Err(Error::BadCgroup(msg)) => {
+                assert_eq!(msg, "No cgroup to create the leaf in - cf. -c option")
+            }
+            res => panic!("expected BadCgroup, got {res:?}"),
+        }
+    }
+
+    #[test]
+    fn relative_cg_dir_is_below_the_base() {
+        assert_eq!(
+            resolve_cg_dir("/sys/fs/cgroup", "my.slice/bench"),
+            "/sys/fs/cgroup/my.slice/bench"
+        );
+        assert_eq!(
+            resolve_cg_dir("/sys/fs/cgroup/", "bench"),
+            "/sys/fs/cgroup/bench"
+        );
+    }
+
+    #[test]
+    fn absolute_cg_dir_is_kept() {
+        assert_eq!(
+            resolve_cg_dir("/sys/fs/cgroup", "/sys/fs/cgroup/my.slice/bench"),
+            "/sys/fs/cgroup/my.slice/bench"
+        );
+        // check_cgroup_dir() rejects it, it's not below the base
+        assert_eq!(resolve_cg_dir("/sys/fs/cgroup", "/tmp/bench"), "/tmp/bench");
+    }
+
+    #[test]
+    fn stale_empty_leaf_is_recreated() {
+        let cg_dir = tempfile::tempdir().unwrap();
+        let leaf_dir = cg_dir.path().join("leaf");
+        // an empty plain directory, rmdir works on it like on an empty cgroup
+        fs::create_dir(&leaf_dir).unwrap();
+        let session = session(&["true"], Options::default());
+        session.replace_stale_leaf(&leaf_dir).unwrap();
+        assert!(leaf_dir.is_dir());
+    }
+
+    #[test]
+    fn stale_populated_leaf_requires_force() {
+        let cg_dir = tempfile::tempdir().unwrap();
+        let leaf_dir = cg_dir.path().join("leaf");
+        fs::create_dir(&leaf_dir).unwrap();
+        fs::write(leaf_dir.join("cgroup.procs"), "4711\n").unwrap();
+        let session = session(&["true"], Options::default());
+        match session.replace_stale_leaf(&leaf_dir) {
+            Err(Error::BadCgroup(msg)) => assert_eq!(
+                msg,
+                format!(
+                    "Stale {} still contains 1 process(es) - cf. --force option",
+                    leaf_dir.display()
+                )
+            ),
+            res => panic!("expected BadCgroup, got {res:?}"),
+        }
+        // left alone
+        assert_eq!(read_procs(&leaf_dir).unwrap(), [4711]);
+    }
+
+    #[test]
+    fn exec_error_is_reported() {
+        let no_cgroup = Options {
+            no_cgroup: true,
+            ..Options::default()
+        };
+        let result = session(&["/nonexistent"], no_cgroup).execute(None).unwrap();
+        let msg = "can't exec '/nonexistent': No such file or directory (os error 2)";
+        assert_eq!(result.exec_error.as_deref(), Some(msg));
+        assert_eq!(result.failure().as_deref(), Some(msg));
+        assert_eq!(result.shell_status(), Some(127));
+    }
+
+    #[test]
+    #[ignore = "requires a delegated cgroup v2 (run below a user@.service)"]
+    fn attach_to_a_sleeper() {
+        // only what it allocates after the move is charged to the leaf
+        let mut sleeper = Command::new("/bin/sh")
+            .arg("-c")
+            .arg("sleep 0.5; x=$(head -c 4000000 /dev/zero | tr '\\0' x); sleep 0.1")
+            .spawn()
+            .unwrap();
+        let pid = sleeper.id() as libc::pid_t;
+        let mut session = Session::new(Options {
+            disable_systemd_run: true,
+            attach: Some(pid),
+            ..Options::default()
+        });
+        session
+            .check_cgroupfs()
+            .and_then(Session::check_cgroup_dir)
+            .and_then(Session::setup_cgroup)
+            .and_then(Session::check_peak_support)
+            .unwrap();
+        let result = session.attach(pid).unwrap();
+        assert!(sleeper.wait().unwrap().success());
+        let peak = result.cg_rss_highwater.unwrap();
+        assert!(peak >= 4_000_000, "peak of {peak} bytes");
+        session.succeeded = true;
+    }
+
+    #[test]
+    fn has_controller_anywhere_in_the_list() {
+        assert!(has_controller("memory pids cpu", "memory"));
+        assert!(has_controller("cpuset memory pids", "memory"));
+        assert!(has_controller("cpuset cpu memory", "memory"));
+        // as read from cgroup.controllers
+        assert!(has_controller("cpuset cpu io memory\n", "memory"));
+        assert!(has_controller("memory\n", "memory"));
+    }
+
+    #[test]
+    fn has_controller_exact_token() {
+        assert!(!has_controller("", "memory"));
+        assert!(!has_controller("\n", "memory"));
+        assert!(!has_controller("cpuset cpu\n", "memory"));
+        assert!(!has_controller("memoryx cpu", "memory"));
+        assert!(!has_controller("cpu xmemory\n", "memory"));
+        assert!(!has_controller("cpuset", "cpu"));
+    }
+}
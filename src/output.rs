@@ -0,0 +1,1742 @@
+//! Rendering of a `Result` in the supported output formats.
+
+use crate::{
+    shell_quote, Capabilities, CgroupStat, Env, Error, Inspection, Limit, Limits, NumaNode,
+    ProcStatus, Ready, Result, Timeout,
+};
+use nix::sys::signal::Signal;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub enum MemUnit {
+    B,
+    KiB,
+    MiB,
+    GiB,
+    KB,
+    MB,
+    GB,
+}
+
+impl MemUnit {
+    /// `unit`, or without one the largest binary unit there's at least one
+    /// of in `bytes`, for the human output.
+    pub fn or_fitting(unit: Option<MemUnit>, bytes: i64) -> MemUnit {
+        unit.unwrap_or(match bytes.unsigned_abs() {
+            b if b >= 1 << 30 => MemUnit::GiB,
+            b if b >= 1 << 20 => MemUnit::MiB,
+            b if b >= 1 << 10 => MemUnit::KiB,
+            _ => MemUnit::B,
+        })
+    }
+
+    pub fn factor(self) -> i64 {
+        match self {
+            MemUnit::B => 1,
+            MemUnit::KiB => 1 << 10,
+            MemUnit::MiB => 1 << 20,
+            MemUnit::GiB => 1 << 30,
+            MemUnit::KB => 1_000,
+            MemUnit::MB => 1_000_000,
+            MemUnit::GB => 1_000_000_000,
+        }
+    }
+
+    pub fn value(self, bytes: i64) -> String {
+        match self {
+            // keep the integral output for the small units
+            MemUnit::B | MemUnit::KiB | MemUnit::KB => (bytes / self.factor()).to_string(),
+            _ => format!("{:.2}", bytes as f64 / self.factor() as f64),
+        }
+    }
+
+    pub fn format(self, bytes: i64) -> String {
+        format!("{} {}", self.value(bytes), self.label())
+    }
+
+    /// Without the space, e.g. 123MiB.
+    fn format_compact(self, bytes: i64) -> String {
+        format!("{}{}", self.value(bytes), self.label())
+    }
+
+    /// Suffix of the csv column names.
+    fn suffix(self) -> &'static str {
+        match self {
+            MemUnit::B => "bytes",
+            MemUnit::KiB => "kib",
+            MemUnit::MiB => "mib",
+            MemUnit::GiB => "gib",
+            MemUnit::KB => "kb",
+            MemUnit::MB => "mb",
+            MemUnit::GB => "gb",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            MemUnit::B => "B",
+            MemUnit::KiB => "KiB",
+            MemUnit::MiB => "MiB",
+            MemUnit::GiB => "GiB",
+            MemUnit::KB => "KB",
+            MemUnit::MB => "MB",
+            MemUnit::GB => "GB",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum TimeUnit {
+    /// fractional seconds
+    #[default]
+    S,
+    Ms,
+    Us,
+    Ns,
+}
+
+impl TimeUnit {
+    /// Integral for everything finer than seconds.
+    pub fn value(self, d: Duration) -> String {
+        match self {
+            TimeUnit::S => format!("{:.6}", d.as_secs_f64()),
+            TimeUnit::Ms => d.as_millis().to_string(),
+            TimeUnit::Us => d.as_micros().to_string(),
+            TimeUnit::Ns => d.as_nanos().to_string(),
+        }
+    }
+
+    /// Suffix of the column names.
+    fn suffix(self) -> &'static str {
+        match self {
+            TimeUnit::S => "seconds",
+            TimeUnit::Ms => "ms",
+            TimeUnit::Us => "us",
+            TimeUnit::Ns => "ns",
+        }
+    }
+
+    /// Suffix of the env and TOML keys, which spell seconds as secs.
+    fn short_suffix(self) -> &'static str {
+        match self {
+            TimeUnit::S => "secs",
+            unit => unit.suffix(),
+        }
+    }
+}
+
+/// What ends a csv row.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RecordSep {
+    #[default]
+    Lf,
+    Nul,
+    None,
+}
+
+impl RecordSep {
+    fn terminator(self) -> &'static str {
+        match self {
+            RecordSep::Lf => "\n",
+            RecordSep::Nul => "\0",
+            RecordSep::None => "",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum OutputFormat {
+    /// one metric per line
+    Human,
+    /// a single row of delimited columns (cf. -d)
+    Csv,
+    /// shell-sourceable CGMEMTIME_NAME=value assignments
+    Env,
+    /// a TOML table per run, in bytes and --time-unit
+    Toml,
+    /// a JSON object per line, in bytes and --time-unit
+    Json,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ColorChoice {
+    /// when stdout is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Debug)]
+pub struct FormatOpts {
+    /// --unit, without it the human output is auto-scaled and the csv is
+    /// in bytes
+    pub unit: Option<MemUnit>,
+    pub time_unit: TimeUnit,
+    pub labels: Vec<(String, String)>,
+    pub delim: char,
+    pub record_sep: RecordSep,
+    pub emit_schema: bool,
+    pub show_limits: bool,
+    pub cgroup_stat: bool,
+    /// --cpu-max, the throttling figures are reported
+    pub cpu_max: bool,
+    pub subtract_baseline: bool,
+    pub sample_fds: bool,
+    pub anon_peak: bool,
+    pub show_exe: bool,
+    pub oneline: bool,
+    pub run_id: String,
+    pub show_run_id: bool,
+    pub env: Option<Env>,
+    /// --no-cgroup, there are no cgroup figures
+    pub no_cgroup: bool,
+    pub color: bool,
+}
+
+impl Default for FormatOpts {
+    fn default() -> Self {
+        FormatOpts {
+            unit: None,
+            time_unit: TimeUnit::default(),
+            labels: Vec::new(),
+            delim: ';',
+            record_sep: RecordSep::default(),
+            emit_schema: false,
+            show_limits: false,
+            cgroup_stat: false,
+            cpu_max: false,
+            subtract_baseline: false,
+            sample_fds: false,
+            anon_peak: false,
+            show_exe: false,
+            oneline: false,
+            run_id: String::new(),
+            show_run_id: false,
+            env: None,
+            no_cgroup: false,
+            color: false,
+        }
+    }
+}
+
+impl FormatOpts {
+    /// `bytes` for the human output, in --unit or auto-scaled.
+    pub fn mem(&self, bytes: i64) -> String {
+        MemUnit::or_fitting(self.unit, bytes).format(bytes)
+    }
+
+    /// Without the space, e.g. 123MiB.
+    fn mem_compact(&self, bytes: i64) -> String {
+        MemUnit::or_fitting(self.unit, bytes).format_compact(bytes)
+    }
+
+    /// `bytes` for the csv, in --unit or in bytes, cf. mem_suffix().
+    pub fn mem_value(&self, bytes: i64) -> String {
+        self.unit.unwrap_or(MemUnit::B).value(bytes)
+    }
+
+    /// Suffix of the memory columns, for the unit of mem_value().
+    fn mem_suffix(&self) -> &'static str {
+        self.unit.unwrap_or(MemUnit::B).suffix()
+    }
+}
+
+/// Version of the csv columns, bump it whenever columns are added, removed
+/// or reordered.
+pub const SCHEMA_VERSION: u32 = 17;
+
+/// Names of the csv columns written with the given options, in order.
+pub fn csv_columns(opts: &FormatOpts) -> Vec<String> {
+    let time = opts.time_unit.suffix();
+    let mem = opts.mem_suffix();
+    let mut cols = vec![
+        format!("user_{time}"),
+        format!("sys_{time}"),
+        format!("wall_{time}"),
+        format!("child_rss_{mem}"),
+        format!("group_mem_{mem}"),
+        format!("group_swap_{mem}"),
+        format!("peak_rss_{mem}"),
+    ];
+    cols.extend(
+        [
+            "run_id",
+            "ok",
+            "signal",
+            "minor_faults",
+            "major_faults",
+            "vol_ctxsw",
+            "invol_ctxsw",
+        ]
+        .map(String::from),
+    );
+    cols.extend([
+        format!("cg_usage_{time}"),
+        format!("cg_user_{time}"),
+        format!("cg_system_{time}"),
+        "pids_peak".to_owned(),
+        "ooms".to_owned(),
+        "oom_kills".to_owned(),
+    ]);
+    if opts.show_limits {
+        cols.extend(["memory_max", "memory_high", "memory_swap_max"].map(|c| format!("{c}_{mem}")));
+    }
+    if opts.cgroup_stat {
+        cols.extend(["nr_descendants", "nr_dying_descendants"].map(String::from));
+    }
+    if opts.cpu_max {
+        cols.extend(["nr_throttled".to_owned(), format!("throttled_{time}")]);
+    }
+    if opts.anon_peak {
+        cols.push(format!("sampled_anon_high_{mem}"));
+    }
+    if opts.show_exe {
+        cols.push("exe".to_owned());
+    }
+    if opts.sample_fds {
+        cols.push("sampled_fds_high".to_owned());
+    }
+    if opts.env.is_some() {
+        cols.extend(["hostname", "kernel", "ncpu"].map(String::from));
+    }
+    if opts.subtract_baseline {
+        cols.extend(["baseline_mem", "group_mem_adjusted"].map(|c| format!("{c}_{mem}")));
+    }
+    cols.extend(opts.labels.iter().map(|(name, _)| name.clone()));
+    cols
+}
+
+pub fn render_schema(opts: &FormatOpts) -> String {
+    format!(
+        "schema={SCHEMA_VERSION}\n{}\n",
+        csv_columns(opts).join(&opts.delim.to_string())
+    )
+}
+
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+/// One `key: value` line of the human output, `alert` highlights the value.
+fn write_kv(
+    f: &mut impl fmt::Write,
+    opts: &FormatOpts,
+    key: &str,
+    value: impl fmt::Display,
+    alert: bool,
+) -> fmt::Result {
+    if !opts.color {
+        return writeln!(f, "{key}: {value}");
+    }
+    let style = if alert { RED } else { BOLD };
+    writeln!(f, "{DIM}{key}:{RESET} {style}{value}{RESET}")
+}
+
+fn write_human(f: &mut impl fmt::Write, r: &Result, opts: &FormatOpts) -> fmt::Result {
+    let mem = |v: Option<i64>| v.map_or("n/a".to_owned(), |v| opts.mem(v));
+    write_kv(f, opts, "user", format!("{:?}", r.child_user), false)?;
+    write_kv(f, opts, "sys", format!("{:?}", r.child_sys), false)?;
+    write_kv(f, opts, "wall", format!("{:?}", r.child_wall), false)?;
+    write_kv(
+        f,
+        opts,
+        "child_RSS_high",
+        mem(Some(r.child_rss_highwater)),
+        false,
+    )?;
+    if opts.no_cgroup {
+        write_kv(
+            f,
+            opts,
+            "group_mem_high",
+            "unavailable (--no-cgroup)",
+            false,
+        )?;
+    } else {
+        write_kv(f, opts, "group_mem_high", mem(r.cg_rss_highwater), false)?;
+        write_kv(f, opts, "group_swap_high", mem(r.cg_swap_peak), false)?;
+    }
+    write_kv(f, opts, "peak_RSS", mem(Some(r.peak_rss)), false)?;
+    let count = |v: Option<u64>| v.map_or("n/a".to_owned(), |v| v.to_string());
+    write_kv(f, opts, "minor_faults", count(r.minor_faults), false)?;
+    write_kv(f, opts, "major_faults", count(r.major_faults), false)?;
+    write_kv(f, opts, "vol_ctxsw", count(r.vol_ctxsw), false)?;
+    write_kv(f, opts, "invol_ctxsw", count(r.invol_ctxsw), false)?;
+    if r.cg_cpu_usage.is_some() {
+        let time = |v: Option<Duration>| v.map_or("n/a".to_owned(), |v| format!("{v:?}"));
+        write_kv(f, opts, "group_cpu", time(r.cg_cpu_usage), false)?;
+        write_kv(f, opts, "group_user", time(r.cg_cpu_user), false)?;
+        write_kv(f, opts, "group_sys", time(r.cg_cpu_system), false)?;
+    }
+    if opts.cpu_max {
+        let throttled = r.throttled.map_or("n/a".to_owned(), |v| format!("{v:?}"));
+        write_kv(f, opts, "nr_throttled", count(r.nr_throttled), false)?;
+        write_kv(f, opts, "throttled", throttled, false)?;
+    }
+    if let Some(pids) = r.pids_peak {
+        write_kv(f, opts, "pids_high", pids, false)?;
+    }
+    if !opts.no_cgroup {
+        write_kv(f, opts, "ooms", r.ooms, r.ooms > 0)?;
+        write_kv(f, opts, "oom_kills", r.oom_kills, r.oom_kills > 0)?;
+    }
+    if let Some(baseline) = r.baseline {
+        write_kv(f, opts, "baseline_mem", mem(Some(baseline)), false)?;
+        write_kv(
+            f,
+            opts,
+            "group_mem_adjusted",
+            mem(r.adjusted_group_mem()),
+            false,
+        )?;
+    }
+    if let Some(parent_peak) = r.parent_peak {
+        write_kv(f, opts, "parent_mem_high", mem(Some(parent_peak)), false)?;
+        write_kv(f, opts, "leaf_mem_high", mem(r.cg_rss_highwater), false)?;
+    }
+    if r.sampled_peak.is_some() {
+        write_kv(f, opts, "sampled_mem_high", mem(r.sampled_peak), false)?;
+        if let Some(at) = r.sampled_peak_at {
+            write_kv(f, opts, "peak_at_ms", at.as_millis(), false)?;
+        }
+    }
+    if let Some(interval) = r.sample_interval_avg {
+        write_kv(
+            f,
+            opts,
+            "sample_interval_avg",
+            format!("{interval:?}"),
+            false,
+        )?;
+    }
+    if let Some(anon) = r.sampled_anon_peak {
+        write_kv(
+            f,
+            opts,
+            "sampled_anon_high",
+            format!("{} (approx.)", opts.mem(anon)),
+            false,
+        )?;
+    }
+    if let Some(status) = &r.proc_status {
+        for (key, bytes) in [
+            ("VmPeak", status.vm_peak),
+            ("VmHWM", status.vm_hwm),
+            ("VmData", status.vm_data),
+            ("VmStk", status.vm_stk),
+        ] {
+            let value = format!("{} (sampled)", opts.mem(bytes as i64));
+            write_kv(f, opts, key, value, false)?;
+        }
+    }
+    if let Some(fds) = r.sampled_fds {
+        write_kv(
+            f,
+            opts,
+            "sampled_fds_high",
+            format!("{fds} (approx.)"),
+            false,
+        )?;
+    }
+    if let Some(limits) = &r.limits {
+        let fmt_limit = |l: Option<Limit>| l.map_or("n/a".to_owned(), |l| l.format(opts));
+        write_kv(f, opts, "memory.max", fmt_limit(limits.memory_max), false)?;
+        write_kv(f, opts, "memory.high", fmt_limit(limits.memory_high), false)?;
+        write_kv(
+            f,
+            opts,
+            "memory.swap.max",
+            fmt_limit(limits.memory_swap_max),
+            false,
+        )?;
+    }
+    if let Some(numa) = &r.numa {
+        for (node, usage) in numa {
+            let usage = format!(
+                "anon {}, file {}",
+                opts.mem(usage.anon as i64),
+                opts.mem(usage.file as i64)
+            );
+            write_kv(f, opts, &format!("numa_node{node}"), usage, false)?;
+        }
+    }
+    if let Some(stat) = &r.cgroup_stat {
+        write_kv(f, opts, "nr_descendants", stat.nr_descendants, false)?;
+        write_kv(
+            f,
+            opts,
+            "nr_dying_descendants",
+            stat.nr_dying_descendants,
+            false,
+        )?;
+    }
+    if let Some(ready) = &r.ready {
+        write_kv(f, opts, "ready_after", format!("{:?}", ready.after), false)?;
+        write_kv(f, opts, "ready_mem_high", mem(ready.mem), false)?;
+    }
+    if let Some(waited_all) = r.waited_all {
+        write_kv(f, opts, "waited_all", format!("{waited_all:?}"), false)?;
+    }
+    if let Some(timeout) = &r.timeout {
+        write_kv(f, opts, "timed_out", "yes", true)?;
+        write_kv(
+            f,
+            opts,
+            "killed_hard",
+            timeout.killed_hard,
+            timeout.killed_hard > 0,
+        )?;
+    }
+    if r.hung {
+        write_kv(f, opts, "hang_detected", "yes", true)?;
+    }
+    if let Some(swap) = r.swap_abort {
+        write_kv(f, opts, "swap_abort", mem(Some(swap)), true)?;
+    }
+    if let Some(value) = r.fail_over {
+        write_kv(f, opts, "fail_over", mem(Some(value)), true)?;
+    }
+    if !r.tripped_events.is_empty() {
+        let events: Vec<String> = r
+            .tripped_events
+            .iter()
+            .map(|(name, delta)| format!("{name} +{delta}"))
+            .collect();
+        write_kv(f, opts, "tripped_events", events.join(", "), true)?;
+    }
+    if let Some(sig) = r.term_signal {
+        write_kv(f, opts, "signal", signal_name(sig), true)?;
+        write_snapshot(f, r, opts)?;
+    }
+    if let Some(attempts) = r.attempts {
+        let (outcome, failed) = if r.oom_kills > 0 {
+            ("still OOM-killed", true)
+        } else {
+            ("succeeded", false)
+        };
+        write_kv(
+            f,
+            opts,
+            "attempts",
+            format!("{attempts} ({outcome})"),
+            failed,
+        )?;
+    }
+    match r.failure() {
+        None => write_kv(f, opts, "status", "ok", false)?,
+        Some(reason) => write_kv(f, opts, "status", format!("failed ({reason})"), true)?,
+    }
+    if opts.show_exe {
+        let exe = r
+            .exe
+            .as_ref()
+            .map_or("not found".to_owned(), |exe| exe.display().to_string());
+        write_kv(f, opts, "exe", exe, false)?;
+    }
+    if opts.show_run_id {
+        write_kv(f, opts, "run_id", &opts.run_id, false)?;
+    }
+    for (name, value) in &opts.labels {
+        write_kv(f, opts, "label", format!("{name}={value}"), false)?;
+    }
+    Ok(())
+}
+
+/// e.g. SIGKILL, just the number if it's unknown
+fn signal_abbrev(sig: i32) -> String {
+    Signal::try_from(sig).map_or_else(|_| sig.to_string(), |name| name.to_string())
+}
+
+/// e.g. SIGKILL (9)
+fn signal_name(sig: i32) -> String {
+    match Signal::try_from(sig) {
+        Ok(_) => format!("{} ({sig})", signal_abbrev(sig)),
+        Err(_) => sig.to_string(),
+    }
+}
+
+/// The main figures as `key=value` pairs on a single line, for logs.
+fn write_oneline(f: &mut impl fmt::Write, r: &Result, opts: &FormatOpts) -> fmt::Result {
+    let cg_peak = r
+        .cg_rss_highwater
+        .map_or("n/a".to_owned(), |v| opts.mem_compact(v));
+    write!(
+        f,
+        "wall={:?} user={:?} sys={:?} peak={} cg_peak={}",
+        r.child_wall,
+        r.child_user,
+        r.child_sys,
+        opts.mem_compact(r.peak_rss),
+        cg_peak
+    )?;
+    if let Some(sig) = r.term_signal {
+        write!(f, " signal={}", signal_abbrev(sig))?;
+    }
+    for (name, value) in &opts.labels {
+        write!(f, " {name}={value}")?;
+    }
+    // println!() in emit() terminates it
+    Ok(())
+}
+
+/// The final stat figures of a killed command.
+fn write_snapshot(f: &mut impl fmt::Write, r: &Result, opts: &FormatOpts) -> fmt::Result {
+    let files = [
+        (
+            "memory.stat",
+            &r.snapshot.memory_stat,
+            &["anon", "file", "kernel", "shmem", "sock"][..],
+        ),
+        (
+            "memory.events",
+            &r.snapshot.memory_events,
+            &["high", "max", "oom", "oom_kill"][..],
+        ),
+        (
+            "cpu.stat",
+            &r.snapshot.cpu_stat,
+            &["usage_usec", "user_usec", "system_usec"][..],
+        ),
+    ];
+    for (file, stat, keys) in files {
+        for &key in keys {
+            let Some(&value) = stat.get(key) else {
+                continue;
+            };
+            let value = if file == "memory.stat" {
+                opts.mem(value as i64)
+            } else {
+                value.to_string()
+            };
+            write_kv(f, opts, &format!("{file} {key}"), value, false)?;
+        }
+    }
+    Ok(())
+}
+
+/// The columns listed by csv_columns()
+fn write_csv(f: &mut impl fmt::Write, r: &Result, opts: &FormatOpts) -> fmt::Result {
+    let cg_time = |v: Option<Duration>| v.map(|v| opts.time_unit.value(v)).unwrap_or_default();
+    // empty with --no-cgroup, there's no memory.events
+    let cg_count = |v: u64| match opts.no_cgroup {
+        true => String::new(),
+        false => v.to_string(),
+    };
+    let mut cols = vec![
+        opts.time_unit.value(r.child_user),
+        opts.time_unit.value(r.child_sys),
+        opts.time_unit.value(r.child_wall),
+        opts.mem_value(r.child_rss_highwater),
+        r.cg_rss_highwater
+            .map(|v| opts.mem_value(v))
+            .unwrap_or_default(),
+        r.cg_swap_peak
+            .map(|v| opts.mem_value(v))
+            .unwrap_or_default(),
+        opts.mem_value(r.peak_rss),
+        opts.run_id.clone(),
+        r.failure().is_none().to_string(),
+        r.term_signal.map(|sig| sig.to_string()).unwrap_or_default(),
+        r.minor_faults.map(|v| v.to_string()).unwrap_or_default(),
+        r.major_faults.map(|v| v.to_string()).unwrap_or_default(),
+        r.vol_ctxsw.map(|v| v.to_string()).unwrap_or_default(),
+        r.invol_ctxsw.map(|v| v.to_string()).unwrap_or_default(),
+        cg_time(r.cg_cpu_usage),
+        cg_time(r.cg_cpu_user),
+        cg_time(r.cg_cpu_system),
+        r.pids_peak.map(|v| v.to_string()).unwrap_or_default(),
+        cg_count(r.ooms),
+        cg_count(r.oom_kills),
+    ];
+    if let Some(limits) = &r.limits {
+        for limit in [
+            limits.memory_max,
+            limits.memory_high,
+            limits.memory_swap_max,
+        ] {
+            cols.push(limit.map(|l| l.value(opts)).unwrap_or_default());
+        }
+    }
+    if opts.cgroup_stat {
+        // empty columns if cgroup.stat was missing
+        let stat = r.cgroup_stat.as_ref();
+        cols.push(
+            stat.map(|s| s.nr_descendants.to_string())
+                .unwrap_or_default(),
+        );
+        cols.push(
+            stat.map(|s| s.nr_dying_descendants.to_string())
+                .unwrap_or_default(),
+        );
+    }
+    if opts.cpu_max {
+        cols.push(r.nr_throttled.map(|v| v.to_string()).unwrap_or_default());
+        cols.push(cg_time(r.throttled));
+    }
+    if opts.anon_peak {
+        cols.push(
+            r.sampled_anon_peak
+                .map(|v| opts.mem_value(v))
+                .unwrap_or_default(),
+        );
+    }
+    if opts.show_exe {
+        cols.push(
+            r.exe
+                .as_ref()
+                .map(|exe| exe.display().to_string())
+                .unwrap_or_default(),
+        );
+    }
+    if opts.sample_fds {
+        cols.push(r.sampled_fds.map(|v| v.to_string()).unwrap_or_default());
+    }
+    if let Some(env) = &opts.env {
+        cols.push(env.hostname.clone());
+        cols.push(env.kernel.clone());
+        cols.push(env.ncpu.to_string());
+    }
+    if opts.subtract_baseline {
+        cols.push(r.baseline.map(|v| opts.mem_value(v)).unwrap_or_default());
+        cols.push(
+            r.adjusted_group_mem()
+                .map(|v| opts.mem_value(v))
+                .unwrap_or_default(),
+        );
+    }
+    // the label names are the column headers
+    cols.extend(opts.labels.iter().map(|(_, value)| value.clone()));
+    write!(
+        f,
+        "{}{}",
+        cols.join(&opts.delim.to_string()),
+        opts.record_sep.terminator()
+    )
+}
+
+/// The default output, one `key: value` line per metric.
+pub fn render_human(r: &Result, opts: &FormatOpts) -> String {
+    let mut out = String::new();
+    if opts.oneline {
+        write_oneline(&mut out, r, opts).unwrap();
+    } else {
+        write_human(&mut out, r, opts).unwrap();
+    }
+    out
+}
+
+pub fn render(r: &Result, format: OutputFormat, opts: &FormatOpts) -> String {
+    match format {
+        OutputFormat::Human => render_human(r, opts),
+        OutputFormat::Csv => {
+            let mut out = String::new();
+            if opts.emit_schema {
+                out.push_str(&format!("# schema={SCHEMA_VERSION}\n"));
+            }
+            write_csv(&mut out, r, opts).unwrap();
+            out
+        }
+        OutputFormat::Env => {
+            let mut out = String::new();
+            write_result_env(&mut out, r, opts).unwrap();
+            out
+        }
+        OutputFormat::Toml => {
+            let mut out = String::new();
+            write_result_toml(&mut out, r, opts).unwrap();
+            out
+        }
+        OutputFormat::Json => {
+            let mut out = String::new();
+            write_result_json(&mut out, r, opts).unwrap();
+            out
+        }
+    }
+}
+
+fn write_inspection_human(
+    f: &mut impl fmt::Write,
+    i: &Inspection,
+    opts: &FormatOpts,
+) -> fmt::Result {
+    let mem = |v: Option<i64>| v.map_or("n/a".to_owned(), |v| opts.mem(v));
+    let bytes = |v: Option<u64>| v.map_or("n/a".to_owned(), |v| opts.mem(v as i64));
+    let time = |v: Option<Duration>| v.map_or("n/a".to_owned(), |v| format!("{v:?}"));
+    let count = |v: Option<u64>| v.map_or("n/a".to_owned(), |v| v.to_string());
+    write_kv(f, opts, "memory.current", mem(i.memory_current), false)?;
+    write_kv(f, opts, "memory.peak", mem(i.memory_peak), false)?;
+    write_kv(f, opts, "cpu_usage", time(i.cpu_usage), false)?;
+    write_kv(f, opts, "cpu_user", time(i.cpu_user), false)?;
+    write_kv(f, opts, "cpu_system", time(i.cpu_system), false)?;
+    write_kv(f, opts, "io_read", bytes(i.io_read_bytes), false)?;
+    write_kv(f, opts, "io_write", bytes(i.io_write_bytes), false)?;
+    write_kv(f, opts, "pids.current", count(i.pids_current), false)?;
+    for (name, value) in &opts.labels {
+        write_kv(f, opts, "label", format!("{name}={value}"), false)?;
+    }
+    Ok(())
+}
+
+/// memory.current and memory.peak, cpu usage, user and system time, read
+/// and written bytes, pids.current, then labels
+/// Names of the --inspect csv columns, the header row of its output.
+fn inspection_csv_columns(opts: &FormatOpts) -> Vec<String> {
+    let time = opts.time_unit.suffix();
+    let mem = opts.mem_suffix();
+    let mut cols = vec![
+        format!("memory_current_{mem}"),
+        format!("memory_peak_{mem}"),
+        format!("cpu_usage_{time}"),
+        format!("cpu_user_{time}"),
+        format!("cpu_system_{time}"),
+        format!("io_read_{mem}"),
+        format!("io_write_{mem}"),
+        "pids_current".to_owned(),
+    ];
+    cols.extend(opts.labels.iter().map(|(name, _)| name.clone()));
+    cols
+}
+
+/// A header row of inspection_csv_columns(), then the row of figures.
+fn write_inspection_csv(f: &mut impl fmt::Write, i: &Inspection, opts: &FormatOpts) -> fmt::Result {
+    let mem = |v: Option<i64>| v.map(|v| opts.mem_value(v)).unwrap_or_default();
+    let bytes = |v: Option<u64>| v.map(|v| opts.mem_value(v as i64)).unwrap_or_default();
+    let secs = |v: Option<Duration>| v.map(|v| opts.time_unit.value(v)).unwrap_or_default();
+    let mut cols = vec![
+        mem(i.memory_current),
+        mem(i.memory_peak),
+        secs(i.cpu_usage),
+        secs(i.cpu_user),
+        secs(i.cpu_system),
+        bytes(i.io_read_bytes),
+        bytes(i.io_write_bytes),
+        i.pids_current.map(|v| v.to_string()).unwrap_or_default(),
+    ];
+    // the label names are the column headers
+    cols.extend(opts.labels.iter().map(|(_, value)| value.clone()));
+    let delim = opts.delim.to_string();
+    let end = opts.record_sep.terminator();
+    write!(
+        f,
+        "{}{end}{}{end}",
+        inspection_csv_columns(opts).join(&delim),
+        cols.join(&delim)
+    )
+}
+
+/// A figure of extra_values(), each structured format spells it its own
+/// way.
+enum Value {
+    Bytes(i64),
+    Time(Duration),
+    Count(u64),
+    Text(String),
+    Flag(bool),
+    Limit(Limit),
+}
+
+impl Value {
+    /// For JSON and TOML, which agree on numbers, strings and booleans.
+    fn json(self, opts: &FormatOpts) -> String {
+        match self {
+            Value::Text(text) => toml_quote(&text),
+            Value::Limit(Limit::Max) => toml_quote("max"),
+            Value::Flag(flag) => flag.to_string(),
+            value => value.env(opts),
+        }
+    }
+
+    fn env(self, opts: &FormatOpts) -> String {
+        match self {
+            Value::Bytes(bytes) | Value::Limit(Limit::Bytes(bytes)) => bytes.to_string(),
+            Value::Time(d) => opts.time_unit.value(d),
+            Value::Count(count) => count.to_string(),
+            Value::Text(text) => shell_quote(&text),
+            // as OK
+            Value::Flag(flag) => u8::from(flag).to_string(),
+            Value::Limit(Limit::Max) => "max".to_owned(),
+        }
+    }
+}
+
+/// The figures of the human output beyond the main ones of the
+/// structured formats, keyed in lower case with the unit, `t` for the
+/// times. Figures of options that weren't given are left out, a figure
+/// that is missing nonetheless is None. Only the stat snapshot of a
+/// killed command stays human-only.
+fn extra_values(r: &Result, opts: &FormatOpts, t: &str) -> Vec<(String, Option<Value>)> {
+    let mut values = Vec::new();
+    if let Some(env) = &opts.env {
+        values.push((
+            "hostname".to_owned(),
+            Some(Value::Text(env.hostname.clone())),
+        ));
+        values.push(("kernel".to_owned(), Some(Value::Text(env.kernel.clone()))));
+        values.push(("ncpu".to_owned(), Some(Value::Count(env.ncpu as u64))));
+    }
+    if opts.show_exe {
+        let exe = r.exe.as_ref().map(|exe| exe.display().to_string());
+        values.push(("exe".to_owned(), exe.map(Value::Text)));
+    }
+    if let Some(err) = &r.exec_error {
+        values.push(("exec_error".to_owned(), Some(Value::Text(err.clone()))));
+    }
+    if let Some(limits) = &r.limits {
+        for (key, limit) in [
+            ("memory_max", limits.memory_max),
+            ("memory_high", limits.memory_high),
+            ("memory_swap_max", limits.memory_swap_max),
+        ] {
+            values.push((key.to_owned(), limit.map(Value::Limit)));
+        }
+    }
+    if opts.cgroup_stat {
+        let stat = r.cgroup_stat.as_ref();
+        values.push((
+            "nr_descendants".to_owned(),
+            stat.map(|s| Value::Count(s.nr_descendants)),
+        ));
+        values.push((
+            "nr_dying_descendants".to_owned(),
+            stat.map(|s| Value::Count(s.nr_dying_descendants)),
+        ));
+    }
+    if opts.cpu_max {
+        values.push(("nr_throttled".to_owned(), r.nr_throttled.map(Value::Count)));
+        values.push((format!("throttled_{t}"), r.throttled.map(Value::Time)));
+    }
+    if opts.subtract_baseline {
+        values.push((
+            "baseline_mem_bytes".to_owned(),
+            r.baseline.map(Value::Bytes),
+        ));
+        values.push((
+            "group_mem_adjusted_bytes".to_owned(),
+            r.adjusted_group_mem().map(Value::Bytes),
+        ));
+    }
+    if let Some(parent_peak) = r.parent_peak {
+        values.push((
+            "parent_mem_high_bytes".to_owned(),
+            Some(Value::Bytes(parent_peak)),
+        ));
+    }
+    if let Some(peak) = r.sampled_peak {
+        values.push((
+            "sampled_mem_high_bytes".to_owned(),
+            Some(Value::Bytes(peak)),
+        ));
+    }
+    if let Some(interval) = r.sample_interval_avg {
+        values.push((
+            format!("sample_interval_avg_{t}"),
+            Some(Value::Time(interval)),
+        ));
+    }
+    if opts.anon_peak {
+        values.push((
+            "sampled_anon_high_bytes".to_owned(),
+            r.sampled_anon_peak.map(Value::Bytes),
+        ));
+    }
+    if let Some(status) = &r.proc_status {
+        for (key, bytes) in [
+            ("vm_peak", status.vm_peak),
+            ("vm_hwm", status.vm_hwm),
+            ("vm_data", status.vm_data),
+            ("vm_stk", status.vm_stk),
+        ] {
+            values.push((format!("{key}_bytes"), Some(Value::Bytes(bytes as i64))));
+        }
+    }
+    if opts.sample_fds {
+        values.push((
+            "sampled_fds_high".to_owned(),
+            r.sampled_fds.map(Value::Count),
+        ));
+    }
+    for (node, usage) in r.numa.iter().flatten() {
+        values.push((
+            format!("numa_node{node}_anon_bytes"),
+            Some(Value::Bytes(usage.anon as i64)),
+        ));
+        values.push((
+            format!("numa_node{node}_file_bytes"),
+            Some(Value::Bytes(usage.file as i64)),
+        ));
+    }
+    if let Some(ready) = &r.ready {
+        values.push((format!("ready_after_{t}"), Some(Value::Time(ready.after))));
+        values.push((
+            "ready_mem_high_bytes".to_owned(),
+            ready.mem.map(Value::Bytes),
+        ));
+        values.push(("ready_stopped".to_owned(), Some(Value::Flag(ready.stopped))));
+    }
+    if let Some(waited_all) = r.waited_all {
+        values.push((format!("waited_all_{t}"), Some(Value::Time(waited_all))));
+    }
+    if let Some(timeout) = &r.timeout {
+        values.push(("timed_out".to_owned(), Some(Value::Flag(true))));
+        values.push((
+            "killed_hard".to_owned(),
+            Some(Value::Count(timeout.killed_hard as u64)),
+        ));
+    }
+    if r.hung {
+        values.push(("hang_detected".to_owned(), Some(Value::Flag(true))));
+    }
+    if let Some(swap) = r.swap_abort {
+        values.push(("swap_abort_bytes".to_owned(), Some(Value::Bytes(swap))));
+    }
+    if let Some(value) = r.fail_over {
+        values.push(("fail_over_bytes".to_owned(), Some(Value::Bytes(value))));
+    }
+    for (name, delta) in &r.tripped_events {
+        values.push((format!("tripped_{name}"), Some(Value::Count(*delta))));
+    }
+    if let Some(attempts) = r.attempts {
+        values.push(("attempts".to_owned(), Some(Value::Count(attempts.into()))));
+    }
+    values
+}
+
+/// The figures of the env, TOML and JSON output, keyed in lower case with
+/// the unit as env and TOML spell them: the main ones, then those of
+/// extra_values().
+fn result_values(r: &Result, opts: &FormatOpts) -> Vec<(String, Option<Value>)> {
+    let t = opts.time_unit.short_suffix();
+    let count = |v: Option<u64>| v.map(Value::Count);
+    let cg_count = |v: u64| (!opts.no_cgroup).then_some(Value::Count(v));
+    let mut values = vec![
+        (format!("user_{t}"), Some(Value::Time(r.child_user))),
+        (format!("sys_{t}"), Some(Value::Time(r.child_sys))),
+        (format!("wall_{t}"), Some(Value::Time(r.child_wall))),
+        (
+            "child_rss_bytes".to_owned(),
+            Some(Value::Bytes(r.child_rss_highwater)),
+        ),
+        (
+            "cg_peak_bytes".to_owned(),
+            r.cg_rss_highwater.map(Value::Bytes),
+        ),
+        (
+            "cg_swap_peak_bytes".to_owned(),
+            r.cg_swap_peak.map(Value::Bytes),
+        ),
+        ("peak_bytes".to_owned(), Some(Value::Bytes(r.peak_rss))),
+        (
+            "peak_at_ms".to_owned(),
+            count(r.sampled_peak_at.map(|at| at.as_millis() as u64)),
+        ),
+        ("run_id".to_owned(), Some(Value::Text(opts.run_id.clone()))),
+        ("ok".to_owned(), Some(Value::Flag(r.failure().is_none()))),
+        ("failure".to_owned(), r.failure().map(Value::Text)),
+        (
+            "exit_code".to_owned(),
+            count(r.exit_code.map(|code| code as u64)),
+        ),
+        (
+            "signal".to_owned(),
+            count(r.term_signal.map(|sig| sig as u64)),
+        ),
+        ("minor_faults".to_owned(), count(r.minor_faults)),
+        ("major_faults".to_owned(), count(r.major_faults)),
+        ("vol_ctxsw".to_owned(), count(r.vol_ctxsw)),
+        ("invol_ctxsw".to_owned(), count(r.invol_ctxsw)),
+        (format!("cg_usage_{t}"), r.cg_cpu_usage.map(Value::Time)),
+        (format!("cg_user_{t}"), r.cg_cpu_user.map(Value::Time)),
+        (format!("cg_system_{t}"), r.cg_cpu_system.map(Value::Time)),
+        ("pids_peak".to_owned(), count(r.pids_peak)),
+        // no memory.events to count them in with --no-cgroup
+        ("ooms".to_owned(), cg_count(r.ooms)),
+        ("oom_kills".to_owned(), cg_count(r.oom_kills)),
+    ];
+    values.extend(extra_values(r, opts, t));
+    values
+}
+
+/// JSON keys with the wording of the csv columns, group_mem, group_swap
+/// and peak_rss,
+/// and seconds spelled out.
+const JSON_KEYS: [(&str, &str); 3] = [
+    ("cg_peak_bytes", "group_mem_bytes"),
+    ("cg_swap_peak_bytes", "group_swap_bytes"),
+    ("peak_bytes", "peak_rss_bytes"),
+];
+
+/// The JSON spelling of a key of result_values().
+fn json_key(key: &str) -> String {
+    if let Some((_, json)) = JSON_KEYS.iter().find(|(k, _)| *k == key) {
+        return json.to_string();
+    }
+    match key.strip_suffix("_secs") {
+        Some(stem) => format!("{stem}_seconds"),
+        None => key.to_owned(),
+    }
+}
+
+/// The key of result_values() a JSON key is spelled for, the inverse of
+/// json_key().
+pub fn key_of_json(json: &str) -> String {
+    if let Some((key, _)) = JSON_KEYS.iter().find(|(_, j)| *j == json) {
+        return key.to_string();
+    }
+    match json.strip_suffix("_seconds") {
+        Some(stem) => format!("{stem}_secs"),
+        None => json.to_owned(),
+    }
+}
+
+/// One `CGMEMTIME_NAME=value` line per variable, values that aren't plain
+/// numbers are quoted. Missing figures are left out.
+fn write_env(
+    f: &mut impl fmt::Write,
+    vars: &[(impl AsRef<str>, Option<String>)],
+    opts: &FormatOpts,
+) -> fmt::Result {
+    for (name, value) in vars {
+        if let Some(value) = value {
+            writeln!(f, "CGMEMTIME_{}={value}", name.as_ref())?;
+        }
+    }
+    for (name, value) in &opts.labels {
+        // label names may contain anything but whitespace and the delimiter
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        writeln!(f, "CGMEMTIME_LABEL_{name}={}", shell_quote(value))?;
+    }
+    Ok(())
+}
+
+/// In bytes and --time-unit, the variable names carry the unit. Cf.
+/// result_values() for what's left out.
+fn write_result_env(f: &mut impl fmt::Write, r: &Result, opts: &FormatOpts) -> fmt::Result {
+    let vars: Vec<(String, Option<String>)> = result_values(r, opts)
+        .into_iter()
+        .map(|(name, value)| (name.to_uppercase(), value.map(|v| v.env(opts))))
+        .collect();
+    write_env(f, &vars, opts)
+}
+
+/// The figures of the --inspect env, TOML and JSON output, keyed like
+/// result_values().
+fn inspection_values(i: &Inspection, opts: &FormatOpts) -> Vec<(String, Option<Value>)> {
+    let t = opts.time_unit.short_suffix();
+    let bytes = |v: Option<i64>| v.map(Value::Bytes);
+    let count = |v: Option<u64>| v.map(Value::Count);
+    let time = |v: Option<Duration>| v.map(Value::Time);
+    vec![
+        ("memory_current_bytes".to_owned(), bytes(i.memory_current)),
+        ("memory_peak_bytes".to_owned(), bytes(i.memory_peak)),
+        (format!("cpu_usage_{t}"), time(i.cpu_usage)),
+        (format!("cpu_user_{t}"), time(i.cpu_user)),
+        (format!("cpu_system_{t}"), time(i.cpu_system)),
+        ("io_read_bytes".to_owned(), count(i.io_read_bytes)),
+        ("io_write_bytes".to_owned(), count(i.io_write_bytes)),
+        ("pids_current".to_owned(), count(i.pids_current)),
+    ]
+}
+
+/// In bytes and --time-unit, like write_result_env().
+fn write_inspection_env(f: &mut impl fmt::Write, i: &Inspection, opts: &FormatOpts) -> fmt::Result {
+    let vars: Vec<(String, Option<String>)> = inspection_values(i, opts)
+        .into_iter()
+        .map(|(name, value)| (name.to_uppercase(), value.map(|v| v.env(opts))))
+        .collect();
+    write_env(f, &vars, opts)
+}
+
+/// A TOML basic string, which is also a valid JSON string.
+fn toml_quote(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// An array-of-tables entry `[[table]]` with one `key = value` line per
+/// TOML value, so that documents collected via --append stay valid.
+/// Missing figures are left out, labels go to a `[table.labels]`
+/// sub-table.
+fn write_toml(
+    f: &mut impl fmt::Write,
+    table: &str,
+    values: &[(impl AsRef<str>, Option<String>)],
+    opts: &FormatOpts,
+) -> fmt::Result {
+    writeln!(f, "[[{table}]]")?;
+    for (key, value) in values {
+        if let Some(value) = value {
+            writeln!(f, "{} = {value}", key.as_ref())?;
+        }
+    }
+    if !opts.labels.is_empty() {
+        writeln!(f, "[{table}.labels]")?;
+        for (name, value) in &opts.labels {
+            writeln!(f, "{} = {}", toml_quote(name), toml_quote(value))?;
+        }
+    }
+    Ok(())
+}
+
+/// In bytes and --time-unit, the keys carry the unit. Cf.
+/// result_values() for what's left out.
+fn write_result_toml(f: &mut impl fmt::Write, r: &Result, opts: &FormatOpts) -> fmt::Result {
+    let values: Vec<(String, Option<String>)> = result_values(r, opts)
+        .into_iter()
+        .map(|(key, value)| (key, value.map(|v| v.json(opts))))
+        .collect();
+    write_toml(f, "run", &values, opts)
+}
+
+/// In bytes and --time-unit, like write_result_toml().
+fn write_inspection_toml(
+    f: &mut impl fmt::Write,
+    i: &Inspection,
+    opts: &FormatOpts,
+) -> fmt::Result {
+    let values: Vec<(String, Option<String>)> = inspection_values(i, opts)
+        .into_iter()
+        .map(|(key, value)| (key, value.map(|v| v.json(opts))))
+        .collect();
+    write_toml(f, "inspection", &values, opts)
+}
+
+/// A single-line object, so that results collected via --append form JSON
+/// Lines. Missing figures are null, labels go to a "labels" object.
+fn write_json(
+    f: &mut impl fmt::Write,
+    values: &[(impl AsRef<str>, Option<String>)],
+    opts: &FormatOpts,
+) -> fmt::Result {
+    let mut fields: Vec<String> = values
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "\"{}\":{}",
+                key.as_ref(),
+                value.as_deref().unwrap_or("null")
+            )
+        })
+        .collect();
+    let labels: Vec<String> = opts
+        .labels
+        .iter()
+        .map(|(name, value)| format!("{}:{}", toml_quote(name), toml_quote(value)))
+        .collect();
+    fields.push(format!("\"labels\":{{{}}}", labels.join(",")));
+    writeln!(f, "{{{}}}", fields.join(","))
+}
+
+/// In bytes and --time-unit, the keys carry the unit and are spelled as
+/// json_key() has it. Cf. result_values() for what's left out.
+fn write_result_json(f: &mut impl fmt::Write, r: &Result, opts: &FormatOpts) -> fmt::Result {
+    let values: Vec<(String, Option<String>)> = result_values(r, opts)
+        .into_iter()
+        .map(|(key, value)| (json_key(&key), value.map(|v| v.json(opts))))
+        .collect();
+    write_json(f, &values, opts)
+}
+
+/// A Result with every figure of result_values() set, for the schema.
+fn schema_result() -> Result {
+    let limit = Some(Limit::Bytes(0));
+    Result {
+        cg_rss_highwater: Some(0),
+        cg_swap_peak: Some(0),
+        minor_faults: Some(0),
+        major_faults: Some(0),
+        vol_ctxsw: Some(0),
+        invol_ctxsw: Some(0),
+        cg_cpu_usage: Some(Duration::ZERO),
+        cg_cpu_user: Some(Duration::ZERO),
+        cg_cpu_system: Some(Duration::ZERO),
+        nr_throttled: Some(0),
+        throttled: Some(Duration::ZERO),
+        pids_peak: Some(0),
+        exe: Some("/bin/true".into()),
+        parent_peak: Some(0),
+        sampled_peak: Some(0),
+        sampled_peak_at: Some(Duration::ZERO),
+        limits: Some(Limits {
+            memory_max: limit,
+            memory_high: limit,
+            memory_swap_max: limit,
+        }),
+        cgroup_stat: Some(CgroupStat {
+            nr_descendants: 0,
+            nr_dying_descendants: 0,
+        }),
+        timeout: Some(Timeout { killed_hard: 0 }),
+        waited_all: Some(Duration::ZERO),
+        attempts: Some(1),
+        numa: Some([(0, NumaNode::default())].into()),
+        ready: Some(Ready {
+            after: Duration::ZERO,
+            mem: Some(0),
+            stopped: false,
+        }),
+        hung: true,
+        swap_abort: Some(0),
+        fail_over: Some(0),
+        sample_interval_avg: Some(Duration::ZERO),
+        sampled_anon_peak: Some(0),
+        proc_status: Some(ProcStatus::default()),
+        sampled_fds: Some(0),
+        baseline: Some(0),
+        term_signal: Some(9),
+        exit_code: Some(0),
+        exec_error: Some(String::new()),
+        tripped_events: vec![("oom_kill".to_owned(), 1)],
+        ..Result::default()
+    }
+}
+
+/// A JSON Schema of what write_result_json() prints, taken from
+/// result_values() of schema_result() with every option that adds a
+/// figure. The figures of the per-NUMA-node and per-event keys go to
+/// patternProperties, those present whatever the options are required.
+pub fn render_json_schema(time_unit: TimeUnit) -> String {
+    let opts = FormatOpts {
+        time_unit,
+        env: Some(Env {
+            hostname: String::new(),
+            kernel: String::new(),
+            ncpu: 1,
+        }),
+        show_exe: true,
+        cgroup_stat: true,
+        cpu_max: true,
+        subtract_baseline: true,
+        sample_fds: true,
+        anon_peak: true,
+        ..FormatOpts::default()
+    };
+    let mut properties = Vec::new();
+    let mut patterns = Vec::new();
+    for (key, value) in result_values(&schema_result(), &opts) {
+        let key = json_key(&key);
+        let types = match value.expect("schema_result() sets every figure") {
+            Value::Time(_) if matches!(time_unit, TimeUnit::S) => r#""number""#,
+            Value::Bytes(_) | Value::Time(_) | Value::Count(_) => r#""integer""#,
+            Value::Text(_) => r#""string""#,
+            Value::Flag(_) => r#""boolean""#,
+            Value::Limit(_) => r#""integer","string""#,
+        };
+        let schema = format!(r#"{{"type":[{types},"null"]}}"#);
+        if let Some(rest) = key.strip_prefix("numa_node0_") {
+            let pattern = format!("^numa_node[0-9]+_{rest}$");
+            patterns.push(format!("    {}: {schema}", toml_quote(&pattern)));
+        } else if key.starts_with("tripped_") {
+            patterns.push(format!("    \"^tripped_[a-z_]+$\": {schema}"));
+        } else {
+            properties.push(format!("    {}: {schema}", toml_quote(&key)));
+        }
+    }
+    properties.push(
+        r#"    "labels": {"type":"object","additionalProperties":{"type":"string"}}"#.to_owned(),
+    );
+    let default_opts = FormatOpts {
+        time_unit,
+        ..FormatOpts::default()
+    };
+    let required: Vec<String> = result_values(&Result::default(), &default_opts)
+        .into_iter()
+        .map(|(key, _)| toml_quote(&json_key(&key)))
+        .chain([toml_quote("labels")])
+        .collect();
+    format!(
+        "{{\n  \"$schema\": \"https://json-schema.org/draft/2020-12/schema\",\n  \
+         \"title\": \"cgmemtime --json\",\n  \"type\": \"object\",\n  \
+         \"properties\": {{\n{}\n  }},\n  \"patternProperties\": {{\n{}\n  }},\n  \
+         \"required\": [{}],\n  \"additionalProperties\": false\n}}\n",
+        properties.join(",\n"),
+        patterns.join(",\n"),
+        required.join(",")
+    )
+}
+
+/// In bytes and --time-unit, like write_result_json().
+fn write_inspection_json(
+    f: &mut impl fmt::Write,
+    i: &Inspection,
+    opts: &FormatOpts,
+) -> fmt::Result {
+    let values: Vec<(String, Option<String>)> = inspection_values(i, opts)
+        .into_iter()
+        .map(|(key, value)| (json_key(&key), value.map(|v| v.json(opts))))
+        .collect();
+    write_json(f, &values, opts)
+}
+
+pub fn render_inspection(i: &Inspection, format: OutputFormat, opts: &FormatOpts) -> String {
+    let mut out = String::new();
+    match format {
+        OutputFormat::Human => write_inspection_human(&mut out, i, opts),
+        OutputFormat::Csv => write_inspection_csv(&mut out, i, opts),
+        OutputFormat::Env => write_inspection_env(&mut out, i, opts),
+        OutputFormat::Toml => write_inspection_toml(&mut out, i, opts),
+        OutputFormat::Json => write_inspection_json(&mut out, i, opts),
+    }
+    .unwrap();
+    out
+}
+
+/// One `file: yes|no` line per interface file and cpu.stat field, or with
+/// JSON a single object with the cpu.stat fields in a nested one. The
+/// other formats get the lines, too.
+pub fn render_capabilities(c: &Capabilities, format: OutputFormat, opts: &FormatOpts) -> String {
+    let mut out = String::new();
+    if let OutputFormat::Json = format {
+        let object = |caps: &[(&str, bool)]| {
+            let fields: Vec<String> = caps
+                .iter()
+                .map(|(name, available)| format!("{}:{available}", toml_quote(name)))
+                .collect();
+            fields.join(",")
+        };
+        out.push_str(&format!(
+            "{{{},\"cpu.stat\":{{{}}}}}\n",
+            object(&c.files),
+            object(&c.cpu_stat)
+        ));
+        return out;
+    }
+    let yes_no = |available: bool| if available { "yes" } else { "no" };
+    for (file, available) in &c.files {
+        write_kv(&mut out, opts, file, yes_no(*available), false).unwrap();
+    }
+    for (field, available) in &c.cpu_stat {
+        let key = format!("cpu.stat {field}");
+        write_kv(&mut out, opts, &key, yes_no(*available), false).unwrap();
+    }
+    out
+}
+
+/// The error as a single-line JSON object with the message, the stable
+/// Error::code() and the path, null unless it's about a single one.
+pub fn render_error(err: &Error) -> String {
+    let path = err.path().map_or("null".to_owned(), |path| {
+        toml_quote(&path.display().to_string())
+    });
+    format!(
+        "{{\"error\":{},\"code\":\"{}\",\"path\":{path}}}\n",
+        toml_quote(&err.to_string()),
+        err.code()
+    )
+}
+
+/// One line per variable of either result: both values, and for numbers
+/// the change, in percent too unless the old value is 0.
+fn write_diff(
+    f: &mut impl fmt::Write,
+    old: &[(String, String)],
+    new: &[(String, String)],
+    opts: &FormatOpts,
+) -> fmt::Result {
+    let lookup = |vars: &[(String, String)], name: &str| {
+        vars.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone())
+    };
+    let mut names: Vec<&String> = old.iter().map(|(name, _)| name).collect();
+    for (name, _) in new {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    for name in names {
+        let (a, b) = (lookup(old, name), lookup(new, name));
+        let value = match (&a, &b) {
+            (Some(a), Some(b)) => match (a.parse::<f64>(), b.parse::<f64>()) {
+                (Ok(x), Ok(y)) => {
+                    let delta = y - x;
+                    let sign = if delta < 0.0 { "-" } else { "+" };
+                    let formatted = if name.ends_with("_BYTES") {
+                        format!("{sign}{}", opts.mem(delta.abs() as i64))
+                    } else if name.ends_with("_SECS") {
+                        format!("{sign}{:?}", Duration::from_secs_f64(delta.abs()))
+                    } else {
+                        format!("{delta:+}")
+                    };
+                    let percent = if x == 0.0 {
+                        String::new()
+                    } else {
+                        format!(", {:+.1}%", delta * 100.0 / x)
+                    };
+                    format!("{a} -> {b} ({formatted}{percent})")
+                }
+                _ if a == b => a.clone(),
+                _ => format!("{a} -> {b}"),
+            },
+            (Some(a), None) => format!("{a} -> missing"),
+            (None, Some(b)) => format!("missing -> {b}"),
+            (None, None) => unreachable!(),
+        };
+        write_kv(f, opts, &name.to_lowercase(), value, false)?;
+    }
+    Ok(())
+}
+
+pub fn render_diff(
+    old: &[(String, String)],
+    new: &[(String, String)],
+    opts: &FormatOpts,
+) -> String {
+    let mut out = String::new();
+    write_diff(&mut out, old, new, opts).unwrap();
+    out
+}
+
+impl fmt::Display for Result {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&render_human(self, &FormatOpts::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A command that exited with 0 after 2s, without any of the optional
+    /// figures.
+    fn plain_result() -> Result {
+        Result {
+            child_user: Duration::from_millis(1500),
+            child_sys: Duration::from_millis(250),
+            child_wall: Duration::from_secs(2),
+            child_rss_highwater: 10 << 20,
+            cg_rss_highwater: Some(12 << 20),
+            peak_rss: 10 << 20,
+            minor_faults: Some(100),
+            major_faults: Some(0),
+            vol_ctxsw: Some(5),
+            invol_ctxsw: Some(1),
+            exit_code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn render_human_plain() {
+        let expected = "\
+user: 1.5s
+sys: 250ms
+wall: 2s
+child_RSS_high: 10.00 MiB
+group_mem_high: 12.00 MiB
+group_swap_high: n/a
+peak_RSS: 10.00 MiB
+minor_faults: 100
+major_faults: 0
+vol_ctxsw: 5
+invol_ctxsw: 1
+ooms: 0
+oom_kills: 0
+status: ok
+";
+        assert_eq!(
+            render_human(&plain_result(), &FormatOpts::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_human_killed() {
+        let r = Result {
+            term_signal: Some(9),
+            exit_code: None,
+            oom_kills: 1,
+            ..plain_result()
+        };
+        let opts = FormatOpts {
+            unit: Some(MemUnit::MiB),
+            labels: vec![("commit".to_owned(), "abc123".to_owned())],
+            ..FormatOpts::default()
+        };
+        let expected = "\
+user: 1.5s
+sys: 250ms
+wall: 2s
+child_RSS_high: 10.00 MiB
+group_mem_high: 12.00 MiB
+group_swap_high: n/a
+peak_RSS: 10.00 MiB
+minor_faults: 100
+major_faults: 0
+vol_ctxsw: 5
+invol_ctxsw: 1
+ooms: 0
+oom_kills: 1
+signal: SIGKILL (9)
+status: failed (1 OOM kill(s))
+label: commit=abc123
+";
+        assert_eq!(render_human(&r, &opts), expected);
+    }
+
+    #[test]
+    fn display_is_render_human() {
+        let r = plain_result();
+        assert_eq!(r.to_string(), render_human(&r, &FormatOpts::default()));
+    }
+
+    #[test]
+    fn json_reads_back_like_env() {
+        // what diff compares
+        let r = Result {
+            cg_cpu_usage: Some(Duration::from_millis(1750)),
+            ..plain_result()
+        };
+        let opts = FormatOpts {
+            run_id: "18de4ed76ec022d3-1713".to_owned(),
+            ..FormatOpts::default()
+        };
+        let env = crate::parse_env_vars(&render(&r, OutputFormat::Env, &opts));
+        let json = crate::parse_json_fields(&render(&r, OutputFormat::Json, &opts)).unwrap();
+        assert!(env.iter().any(|(name, _)| name == "CG_PEAK_BYTES"));
+        assert!(env.iter().any(|(name, _)| name == "CG_USAGE_SECS"));
+        assert_eq!(json, env);
+    }
+
+    #[test]
+    fn no_oom_counts_without_a_cgroup() {
+        let opts = FormatOpts {
+            no_cgroup: true,
+            ..FormatOpts::default()
+        };
+        let r = plain_result();
+        let json = render(&r, OutputFormat::Json, &opts);
+        assert!(json.contains(r#""ooms":null,"oom_kills":null"#));
+        assert!(!render(&r, OutputFormat::Env, &opts).contains("OOM"));
+        assert!(!render(&r, OutputFormat::Toml, &opts).contains("oom"));
+        assert!(render(&r, OutputFormat::Csv, &opts).ends_with(";;\n"));
+    }
+
+    #[test]
+    fn csv_is_in_bytes_unless_unit() {
+        let r = plain_result();
+        for (unit, column, value) in [
+            (None, "child_rss_bytes", "10485760"),
+            (Some(MemUnit::MiB), "child_rss_mib", "10.00"),
+        ] {
+            let opts = FormatOpts {
+                unit,
+                ..FormatOpts::default()
+            };
+            let columns = csv_columns(&opts);
+            let row = render(&r, OutputFormat::Csv, &opts);
+            let cells: Vec<&str> = row.trim_end().split(';').collect();
+            assert_eq!(columns.len(), cells.len());
+            let i = columns.iter().position(|c| c == column).unwrap();
+            assert_eq!(cells[i], value);
+        }
+    }
+
+    #[test]
+    fn inspection_csv_names_the_labels_in_the_header() {
+        let i = Inspection {
+            memory_current: Some(4 << 10),
+            pids_current: Some(3),
+            ..Default::default()
+        };
+        let opts = FormatOpts {
+            labels: vec![("host".to_owned(), "a".to_owned())],
+            ..FormatOpts::default()
+        };
+        assert_eq!(
+            render_inspection(&i, OutputFormat::Csv, &opts),
+            "memory_current_bytes;memory_peak_bytes;cpu_usage_seconds;cpu_user_seconds;\
+             cpu_system_seconds;io_read_bytes;io_write_bytes;pids_current;host\n4096;;;;;;;3;a\n"
+        );
+    }
+
+    /// The keys of a line of JSON, those of nested objects included.
+    fn json_keys(line: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '"' {
+                let s = crate::parse_json_string(&mut chars).unwrap();
+                if chars.peek() == Some(&':') {
+                    keys.push(s);
+                }
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn json_schema_has_every_key() {
+        let rich = Result {
+            limits: Some(Limits {
+                memory_max: Some(Limit::Max),
+                memory_high: None,
+                memory_swap_max: Some(Limit::Bytes(0)),
+            }),
+            numa: Some([(0, NumaNode::default()), (1, NumaNode::default())].into()),
+            ready: Some(Ready {
+                after: Duration::from_millis(20),
+                mem: None,
+                stopped: true,
+            }),
+            timeout: Some(Timeout { killed_hard: 1 }),
+            hung: true,
+            tripped_events: vec![("max".to_owned(), 3), ("oom_group_kill".to_owned(), 1)],
+            attempts: Some(2),
+            exec_error: Some("No such file or directory".to_owned()),
+            ..plain_result()
+        };
+        for time_unit in [TimeUnit::S, TimeUnit::Ms] {
+            let schema = render_json_schema(time_unit);
+            let rich_opts = FormatOpts {
+                time_unit,
+                show_exe: true,
+                cgroup_stat: true,
+                cpu_max: true,
+                subtract_baseline: true,
+                sample_fds: true,
+                anon_peak: true,
+                ..FormatOpts::default()
+            };
+            let plain_opts = FormatOpts {
+                time_unit,
+                ..FormatOpts::default()
+            };
+            for (r, opts) in [(&plain_result(), &plain_opts), (&rich, &rich_opts)] {
+                for key in json_keys(&render(r, OutputFormat::Json, opts)) {
+                    let key = match key.strip_prefix("numa_node") {
+                        Some(rest) => {
+                            let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+                            format!("^numa_node[0-9]+{rest}$")
+                        }
+                        None if key.starts_with("tripped_") => "^tripped_[a-z_]+$".to_owned(),
+                        None => key,
+                    };
+                    assert!(
+                        schema.contains(&format!("\"{key}\":")),
+                        "{key} not in the schema"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Polls the leaf cgroup in a background thread while the command runs.
+
+use crate::output::MemUnit;
+use crate::{kill_cgroup, read_cg_value, read_keyed, read_procs};
+use nix::libc;
+use nix::time::{clock_gettime, ClockId};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{read_dir, File};
+use std::io::{BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The --max-sample-overhead back-off doesn't grow the interval beyond
+/// that, otherwise short peaks would go unnoticed for good.
+const MAX_BACKOFF_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A memory.current reading, for the run_with_progress() callback.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    /// since the command was started
+    pub elapsed: Duration,
+    /// memory.current of the leaf in bytes
+    pub memory_current: i64,
+    /// highest memory.current sampled so far
+    pub max_current: i64,
+}
+
+/// Gets each Sample, ControlFlow::Break stops the sampling.
+pub type Progress = Box<dyn FnMut(&Sample) -> ControlFlow<()> + Send>;
+
+/// What the sampler does apart from tracking memory.current.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Probes {
+    /// SIGKILL everything in the cgroup once its CPU usage hasn't advanced
+    /// for that long
+    pub hang_timeout: Option<Duration>,
+    /// show the current memory and CPU usage on stderr
+    pub follow: bool,
+    /// --unit of the --follow memory, auto-scaled without
+    pub unit: Option<MemUnit>,
+    /// count the open file descriptors of all processes in the cgroup
+    pub count_fds: bool,
+    /// track anon from memory.stat
+    pub anon: bool,
+    /// sample /proc/PID/status of the command
+    pub proc_status: Option<libc::pid_t>,
+    /// SIGKILL everything in the cgroup once memory.swap.current exceeds
+    /// that many bytes
+    pub max_swap: Option<u64>,
+    /// back off the interval while the sampler's CPU time exceeds this
+    /// fraction of the wall-clock time
+    pub max_overhead: Option<f64>,
+}
+
+/// Highest Vm* figures from /proc/PID/status, in bytes.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ProcStatus {
+    pub vm_peak: u64,
+    pub vm_hwm: u64,
+    pub vm_data: u64,
+    pub vm_stk: u64,
+}
+
+impl ProcStatus {
+    fn record(&mut self, pid: libc::pid_t) {
+        // gone, or a zombie without the Vm* lines, once the command exits
+        let Ok(status) = fs::read_to_string(format!("/proc/{pid}/status")) else {
+            return;
+        };
+        for line in status.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let field = match key {
+                "VmPeak" => &mut self.vm_peak,
+                "VmHWM" => &mut self.vm_hwm,
+                "VmData" => &mut self.vm_data,
+                "VmStk" => &mut self.vm_stk,
+                _ => continue,
+            };
+            // e.g. VmPeak:	   12345 kB
+            if let Some(kib) = value
+                .trim()
+                .strip_suffix(" kB")
+                .and_then(|v| v.trim().parse::<u64>().ok())
+            {
+                *field = (*field).max(kib * 1024);
+            }
+        }
+    }
+}
+
+/// Logs every increment of the memory.events counters to the
+/// --trace-events file.
+#[derive(Debug)]
+pub struct EventTrace {
+    file: File,
+    last: HashMap<String, u64>,
+}
+
+impl EventTrace {
+    pub fn new(file: File) -> EventTrace {
+        EventTrace {
+            file,
+            last: HashMap::new(),
+        }
+    }
+
+    fn record(&mut self, leaf_dir: &Path, elapsed: Duration) {
+        if let Ok(events) = read_keyed(&leaf_dir.join("memory.events")) {
+            self.update(&events, elapsed);
+        }
+    }
+
+    /// Log the counters in `events` that are higher than last seen.
+    pub fn update(&mut self, events: &HashMap<String, u64>, elapsed: Duration) {
+        let mut increments: Vec<_> = events
+            .iter()
+            .filter(|&(key, &value)| value > self.last.get(key).copied().unwrap_or_default())
+            .collect();
+        // same order within a sample from run to run
+        increments.sort();
+        for (key, &value) in increments {
+            let before = self.last.insert(key.clone(), value).unwrap_or_default();
+            // a full disk shouldn't fail the measurement
+            let _ = writeln!(
+                self.file,
+                "{:.3} {key} {value} (+{})",
+                elapsed.as_secs_f64(),
+                value - before
+            );
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Samples {
+    /// highest memory.current seen
+    pub max_current: Option<i64>,
+    /// time since the start of the command when max_current was seen first
+    pub max_current_at: Option<Duration>,
+    /// highest number of open file descriptors seen
+    pub max_fds: Option<u64>,
+    /// highest anon in memory.stat seen
+    pub max_anon: Option<u64>,
+    pub proc_status: Option<ProcStatus>,
+    pub events: Option<EventTrace>,
+    /// gets a csv line per memory.current sample, for --trace-out
+    trace: Option<BufWriter<File>>,
+    /// last usage_usec from cpu.stat and when it last advanced
+    cpu_usage: Option<(u64, Instant)>,
+    /// usage_usec and the time of the previous sample
+    last_tick: Option<(u64, Instant)>,
+    /// of all cores between the last two samples
+    cpu_percent: Option<f64>,
+    current: Option<i64>,
+    /// set when the --hang-timeout watchdog killed the cgroup
+    pub hung: bool,
+    /// memory.swap.current that made --abort-swap-above kill the cgroup
+    pub swap_abort: Option<u64>,
+    /// wall-clock time per sample
+    pub avg_interval: Option<Duration>,
+}
+
+impl Samples {
+    fn record(&mut self, leaf_dir: &Path, elapsed: Duration) {
+        // the file may briefly be unreadable while the cgroup drains
+        if let Some(current) = read_cg_value(&leaf_dir.join("memory.current"))
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            if self.max_current.is_none_or(|max| current > max) {
+                self.max_current = Some(current);
+                self.max_current_at = Some(elapsed);
+            }
+            self.current = Some(current);
+            if let Some(trace) = &mut self.trace {
+                // a full disk shouldn't fail the measurement
+                let _ = writeln!(trace, "{:.3},{current}", elapsed.as_secs_f64());
+            }
+        }
+        if let Some(&usage) = read_keyed(&leaf_dir.join("cpu.stat"))
+            .ok()
+            .as_ref()
+            .and_then(|stat| stat.get("usage_usec"))
+        {
+            match self.cpu_usage {
+                Some((last, _)) if last == usage => (),
+                _ => self.cpu_usage = Some((usage, Instant::now())),
+            }
+            let now = Instant::now();
+            if let Some((last, then)) = self.last_tick {
+                let wall = now.duration_since(then).as_micros() as f64;
+                let cores = thread::available_parallelism().map_or(1, |n| n.get()) as f64;
+                self.cpu_percent =
+                    Some(usage.saturating_sub(last) as f64 * 100.0 / (wall * cores).max(1.0));
+            }
+            self.last_tick = Some((usage, now));
+        }
+    }
+
+    /// Overwrite the --follow status line on stderr.
+    fn print_status(&self, unit: Option<MemUnit>) {
+        let mem = self
+            .current
+            .map_or("n/a".to_owned(), |v| MemUnit::or_fitting(unit, v).format(v));
+        let cpu = self
+            .cpu_percent
+            .map_or("n/a".to_owned(), |v| format!("{v:.1}%"));
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r\x1b[Kmem: {mem}  cpu: {cpu}");
+        let _ = stderr.flush();
+    }
+
+    fn record_anon(&mut self, leaf_dir: &Path) {
+        if let Some(&anon) = read_keyed(&leaf_dir.join("memory.stat"))
+            .ok()
+            .as_ref()
+            .and_then(|stat| stat.get("anon"))
+        {
+            self.max_anon = Some(self.max_anon.map_or(anon, |m: u64| m.max(anon)));
+        }
+    }
+
+    fn record_fds(&mut self, leaf_dir: &Path) {
+        // processes may exit between reading cgroup.procs and their fd dir
+        let fds = read_procs(leaf_dir)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|pid| read_dir(format!("/proc/{pid}/fd")).ok())
+            .map(|dir| dir.count() as u64)
+            .sum();
+        self.max_fds = Some(self.max_fds.map_or(fds, |m: u64| m.max(fds)));
+    }
+
+    /// How long the cgroup hasn't used any CPU time.
+    fn stalled_for(&self) -> Duration {
+        self.cpu_usage
+            .map_or(Duration::ZERO, |(_, since)| since.elapsed())
+    }
+}
+
+/// CPU time the calling thread has used.
+fn thread_cpu_time() -> Duration {
+    clock_gettime(ClockId::CLOCK_THREAD_CPUTIME_ID).map_or(Duration::ZERO, |ts| {
+        Duration::new(ts.tv_sec() as u64, ts.tv_nsec() as u32)
+    })
+}
+
+pub struct Sampler {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<Samples>,
+    follow: bool,
+}
+
+impl Sampler {
+    /// Must only be started after the clone, the child shouldn't inherit a
+    /// multi-threaded parent. The sample times are relative to `cmd_started`.
+    pub fn start(
+        leaf_dir: PathBuf,
+        cmd_started: Instant,
+        mut interval: Duration,
+        probes: Probes,
+        events: Option<EventTrace>,
+        trace: Option<BufWriter<File>>,
+        mut progress: Option<Progress>,
+    ) -> Sampler {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut samples = Samples {
+                events,
+                trace,
+                ..Default::default()
+            };
+            let started = Instant::now();
+            let mut ticks = 0;
+            loop {
+                ticks += 1;
+                let elapsed = cmd_started.elapsed();
+                samples.record(&leaf_dir, elapsed);
+                if let (Some(on_sample), Some(memory_current), Some(max_current)) =
+                    (&mut progress, samples.current, samples.max_current)
+                {
+                    let sample = Sample {
+                        elapsed,
+                        memory_current,
+                        max_current,
+                    };
+                    if on_sample(&sample).is_break() {
+                        break;
+                    }
+                }
+                if let Some(pid) = probes.proc_status {
+                    samples
+                        .proc_status
+                        .get_or_insert_with(Default::default)
+                        .record(pid);
+                }
+                if let Some(events) = &mut samples.events {
+                    events.record(&leaf_dir, cmd_started.elapsed());
+                }
+                if probes.anon {
+                    samples.record_anon(&leaf_dir);
+                }
+                if probes.count_fds {
+                    samples.record_fds(&leaf_dir);
+                }
+                if probes.follow {
+                    samples.print_status(probes.unit);
+                }
+                if probes
+                    .hang_timeout
+                    .is_some_and(|timeout| samples.stalled_for() >= timeout)
+                {
+                    kill_cgroup(&leaf_dir);
+                    samples.hung = true;
+                    break;
+                }
+                if let Some(max_swap) = probes.max_swap {
+                    let swap = read_cg_value(&leaf_dir.join("memory.swap.current"))
+                        .ok()
+                        .and_then(|v| v.parse::<u64>().ok());
+                    if let Some(swap) = swap.filter(|&swap| swap > max_swap) {
+                        kill_cgroup(&leaf_dir);
+                        samples.swap_abort = Some(swap);
+                        break;
+                    }
+                }
+                if let Some(max_overhead) = probes.max_overhead {
+                    let overhead = thread_cpu_time().as_secs_f64()
+                        / started.elapsed().as_secs_f64().max(f64::MIN_POSITIVE);
+                    if overhead > max_overhead {
+                        // never shrinks an interval that was given larger
+                        interval = (interval * 2).min(MAX_BACKOFF_INTERVAL).max(interval);
+                    }
+                }
+                if stopped.recv_timeout(interval) != Err(mpsc::RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            samples.avg_interval = Some(started.elapsed() / ticks);
+            if let Some(trace) = &mut samples.trace {
+                let _ = trace.flush();
+            }
+            samples
+        });
+        Sampler {
+            stop,
+            handle,
+            follow: probes.follow,
+        }
+    }
+
+    /// A panic on the sampler thread, e.g. in the Progress callback, is
+    /// re-raised here with its original payload.
+    pub fn stop(self) -> Samples {
+        let _ = self.stop.send(());
+        let samples = self
+            .handle
+            .join()
+            .unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+        if self.follow {
+            eprintln!();
+        }
+        samples
+    }
+}